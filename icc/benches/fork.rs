@@ -0,0 +1,51 @@
+//! Measures `IntCodeProgram::fork` for growing program sizes, next to a full
+//! copy of the same memory, which is what cloning cost before pages were
+//! shared.
+//!
+//! Run with `cargo bench -p icc --bench fork`.
+
+use icc::interpreter::IntCodeProgram;
+use icc::Memory;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const SIZES: &[usize] = &[1 << 10, 1 << 14, 1 << 18, 1 << 22];
+const FORKS: u32 = 1000;
+
+fn time_per_iteration<F: FnMut()>(mut f: F) -> Duration {
+	let start = Instant::now();
+	for _ in 0..FORKS {
+		f();
+	}
+	start.elapsed() / FORKS
+}
+
+fn main() {
+	println!(
+		"{:>10} {:>12} {:>12} {:>14}",
+		"cells", "fork", "full copy", "fork + write"
+	);
+	for &size in SIZES {
+		// Halt right away; the rest of the memory is data.
+		let mut data: Vec<i64> = vec![1; size];
+		data[0] = 99;
+		let program = IntCodeProgram::from_memory(Memory::new(data.clone()), Vec::new());
+
+		let fork = time_per_iteration(|| {
+			black_box(program.fork());
+		});
+		let full_copy = time_per_iteration(|| {
+			black_box(data.clone());
+		});
+		// The first write copies the page table and the page written to.
+		let fork_and_write = time_per_iteration(|| {
+			let mut fork = program.fork();
+			fork.memory_mut().write(size / 2, 7);
+			black_box(fork);
+		});
+		println!(
+			"{:>10} {:>12.2?} {:>12.2?} {:>14.2?}",
+			size, fork, full_copy, fork_and_write
+		);
+	}
+}
@@ -0,0 +1,94 @@
+//! Compares the paged `Memory` with the previous layout that kept every
+//! address past the loaded program in a `HashMap`.
+//!
+//! Run with `cargo bench -p icc --bench memory`.
+
+use icc::Memory;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const PROGRAM_SIZE: usize = 4096;
+const STACK_DEPTH: usize = 200_000;
+const ROUNDS: usize = 10;
+
+/// The memory layout `Memory` used before it was paged.
+struct HashMapMemory {
+	initial_data: Vec<i64>,
+	expanded_memory: HashMap<usize, i64>,
+}
+
+impl HashMapMemory {
+	fn new(p_data: Vec<i64>) -> HashMapMemory {
+		HashMapMemory {
+			initial_data: p_data,
+			expanded_memory: HashMap::new(),
+		}
+	}
+
+	fn read(&self, index: usize) -> i64 {
+		if index >= self.initial_data.len() {
+			match self.expanded_memory.get(&index) {
+				Some(value) => *value,
+				None => 0,
+			}
+		} else {
+			self.initial_data[index]
+		}
+	}
+
+	fn write(&mut self, index: usize, value: i64) {
+		if index >= self.initial_data.len() {
+			self.expanded_memory.insert(index, value);
+		} else {
+			self.initial_data[index] = value;
+		}
+	}
+}
+
+/// Pushes `STACK_DEPTH` values right after the program, the way relative
+/// base addressing grows a stack, then pops them all back.
+fn stack_workload(read: impl Fn(usize) -> i64, mut write: impl FnMut(usize, i64)) -> i64 {
+	let mut sum = 0;
+	for round in 0..ROUNDS {
+		for i in 0..STACK_DEPTH {
+			write(PROGRAM_SIZE + i, (i + round) as i64);
+		}
+		for i in (0..STACK_DEPTH).rev() {
+			sum += read(PROGRAM_SIZE + i);
+		}
+	}
+	sum
+}
+
+fn time<F: FnOnce() -> i64>(name: &str, f: F) -> Duration {
+	let start = Instant::now();
+	black_box(f());
+	let elapsed = start.elapsed();
+	println!("{:<10} {:>10.2?}", name, elapsed);
+	elapsed
+}
+
+fn main() {
+	let program = vec![1; PROGRAM_SIZE];
+
+	let hash_map = time("hash map", || {
+		let memory = RefCell::new(HashMapMemory::new(program.clone()));
+		stack_workload(
+			|index| memory.borrow().read(index),
+			|index, value| memory.borrow_mut().write(index, value),
+		)
+	});
+	let paged = time("paged", || {
+		let memory = RefCell::new(Memory::new(program.clone()));
+		stack_workload(
+			|index| memory.borrow().read(index),
+			|index, value| memory.borrow_mut().write(index, value),
+		)
+	});
+	println!(
+		"paged memory is {:.1}x faster",
+		hash_map.as_secs_f64() / paged.as_secs_f64()
+	);
+}
@@ -0,0 +1,73 @@
+use crate::interpreter::{ExecutionEvent, IntCodeProgram, VmError};
+use crate::word::Word;
+
+/// Character codes of `line` followed by a newline, as a program reading
+/// ASCII text expects them.
+pub fn encode_line<W: Word>(line: &str) -> Vec<W> {
+	line.chars()
+		.chain(Some('\n'))
+		.map(|c| W::from_i64(c as i64))
+		.collect()
+}
+
+/// The character an output value stands for, or `None` if it is outside
+/// `0..=127`.
+pub fn decode<W: Word>(value: &W) -> Option<char> {
+	match value.to_i64() {
+		Some(code @ 0..=127) => Some(code as u8 as char),
+		_ => None,
+	}
+}
+
+/// Talks to a program that reads and prints ASCII text: lines are fed as
+/// character codes and outputs are turned back into text.
+pub struct AsciiIo<'a, W = i64> {
+	program: &'a mut IntCodeProgram<W>,
+	at_line_start: bool,
+}
+
+impl<'a, W: Word> AsciiIo<'a, W> {
+	pub fn new(program: &'a mut IntCodeProgram<W>) -> AsciiIo<'a, W> {
+		AsciiIo {
+			program,
+			at_line_start: true,
+		}
+	}
+
+	pub fn program(&mut self) -> &mut IntCodeProgram<W> {
+		self.program
+	}
+
+	/// Queues a line of input, followed by a newline.
+	pub fn push_line(&mut self, line: &str) {
+		for value in encode_line(line) {
+			self.program.push_input(value);
+		}
+	}
+
+	/// Runs the program until any event other than output, passing every
+	/// output to `print` as text. Values outside `0..=127` are printed as
+	/// numbers on a line of their own.
+	pub fn run_until_event<F: FnMut(&str)>(
+		&mut self,
+		mut print: F,
+	) -> Result<ExecutionEvent<W>, VmError<W>> {
+		loop {
+			let value = match self.program.run_until_event()? {
+				ExecutionEvent::Output(value) => value,
+				event => return Ok(event),
+			};
+			match decode(&value) {
+				Some(c) => {
+					print(c.encode_utf8(&mut [0; 4]));
+					self.at_line_start = c == '\n';
+				}
+				None if self.at_line_start => print(&format!("{}\n", value)),
+				None => {
+					print(&format!("\n{}\n", value));
+					self.at_line_start = true;
+				}
+			}
+		}
+	}
+}
@@ -0,0 +1,163 @@
+use crate::instructions::Instructions;
+use crate::interpreter::StepInfo;
+use crate::source_map::SourceMap;
+use crate::trace::Tracer;
+use crate::word::Word;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+/// Records which addresses were executed and which way every `JMP` and
+/// `JMPF` went. Set it with `IntCodeProgram::set_tracer`, wrapped in an
+/// `Arc<Mutex<_>>` to read the results back.
+#[derive(Clone, Default, Debug)]
+pub struct Coverage {
+	executions: HashMap<usize, u64>,
+	/// Times each branch jumped and fell through.
+	branches: HashMap<usize, (u64, u64)>,
+}
+
+/// Coverage of one source line.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+struct LineCoverage {
+	executions: u64,
+	branch: Option<(u64, u64)>,
+}
+
+impl<W: Word> Tracer<W> for Coverage {
+	fn trace(&mut self, _step_number: u64, step: &StepInfo<W>, _relative_base: &W) {
+		*self.executions.entry(step.address).or_insert(0) += 1;
+		let taken = match step.instruction {
+			Instructions::JMP => !step.values()[0].is_zero(),
+			Instructions::JMPF => step.values()[0].is_zero(),
+			_ => return,
+		};
+		let branch = self.branches.entry(step.address).or_insert((0, 0));
+		if taken {
+			branch.0 += 1;
+		} else {
+			branch.1 += 1;
+		}
+	}
+}
+
+impl Coverage {
+	pub fn new() -> Coverage {
+		Coverage::default()
+	}
+
+	/// Number of times the instruction at `address` was executed.
+	pub fn executions(&self, address: usize) -> u64 {
+		self.executions.get(&address).copied().unwrap_or(0)
+	}
+
+	/// Times the branch at `address` jumped and fell through, or `None` if
+	/// it never ran.
+	pub fn branch(&self, address: usize) -> Option<(u64, u64)> {
+		self.branches.get(&address).copied()
+	}
+
+	/// Coverage of every source line holding an instruction, by line number.
+	fn lines(&self, source_map: &SourceMap) -> BTreeMap<usize, LineCoverage> {
+		let mut lines: BTreeMap<usize, LineCoverage> = BTreeMap::new();
+		for (address, line) in source_map.instructions() {
+			let coverage = lines.entry(line).or_default();
+			coverage.executions += self.executions(address);
+			let branch_instruction = matches!(
+				source_map.instruction(address),
+				Some(Instructions::JMP) | Some(Instructions::JMPF)
+			);
+			if branch_instruction {
+				let (taken, not_taken) = self.branch(address).unwrap_or((0, 0));
+				let (total_taken, total_not_taken) = coverage.branch.unwrap_or((0, 0));
+				coverage.branch = Some((total_taken + taken, total_not_taken + not_taken));
+			}
+		}
+		lines
+	}
+
+	/// Formats every source line with how often it ran. Lines with an
+	/// instruction that never ran are marked with `#####`, and branches
+	/// show how often they jumped and fell through.
+	pub fn report(&self, source_map: &SourceMap) -> String {
+		let lines = self.lines(source_map);
+		let lines_hit = lines.values().filter(|line| line.executions > 0).count();
+		let branches: Vec<(u64, u64)> = lines.values().filter_map(|line| line.branch).collect();
+		let branches_hit: usize = branches
+			.iter()
+			.map(|(taken, not_taken)| (*taken > 0) as usize + (*not_taken > 0) as usize)
+			.sum();
+		let mut report = String::new();
+		writeln!(
+			report,
+			"Coverage of {}: {}/{} lines, {}/{} branches",
+			source_map.file(),
+			lines_hit,
+			lines.len(),
+			branches_hit,
+			branches.len() * 2
+		)
+		.unwrap();
+		let mut line_number = 1;
+		while let Some(source) = source_map.source_line(line_number) {
+			let count = match lines.get(&line_number) {
+				Some(line) if line.executions == 0 => String::from("#####"),
+				Some(line) => line.executions.to_string(),
+				None => String::new(),
+			};
+			let branch = match lines.get(&line_number).and_then(|line| line.branch) {
+				Some((taken, not_taken)) => {
+					format!("  [jumped {}, fell through {}]", taken, not_taken)
+				}
+				None => String::new(),
+			};
+			writeln!(
+				report,
+				"{:>9} | {:>4}: {}{}",
+				count, line_number, source, branch
+			)
+			.unwrap();
+			line_number += 1;
+		}
+		report
+	}
+
+	/// Writes an lcov tracefile for the source the map was built from. Each
+	/// `JMP`/`JMPF` line gets two branch entries: jumped and fell through.
+	pub fn write_lcov(&self, source_map: &SourceMap, mut writer: impl Write) -> io::Result<()> {
+		let lines = self.lines(source_map);
+		writeln!(writer, "TN:")?;
+		writeln!(writer, "SF:{}", source_map.file())?;
+		let mut branches_found = 0;
+		let mut branches_hit = 0;
+		for (line, coverage) in &lines {
+			if let Some((taken, not_taken)) = coverage.branch {
+				for (branch, count) in [taken, not_taken].iter().enumerate() {
+					// lcov uses `-` for branches whose line never ran.
+					let count = match coverage.executions {
+						0 => String::from("-"),
+						_ => count.to_string(),
+					};
+					writeln!(writer, "BRDA:{},0,{},{}", line, branch, count)?;
+				}
+				branches_found += 2;
+				branches_hit += (taken > 0) as usize + (not_taken > 0) as usize;
+			}
+		}
+		if branches_found > 0 {
+			writeln!(writer, "BRF:{}", branches_found)?;
+			writeln!(writer, "BRH:{}", branches_hit)?;
+		}
+		for (line, coverage) in &lines {
+			writeln!(writer, "DA:{},{}", line, coverage.executions)?;
+		}
+		writeln!(writer, "LF:{}", lines.len())?;
+		writeln!(
+			writer,
+			"LH:{}",
+			lines.values().filter(|line| line.executions > 0).count()
+		)?;
+		writeln!(writer, "end_of_record")?;
+		writer.flush()
+	}
+}
@@ -1,4 +1,5 @@
-use std::collections::HashMap;
+use crate::word::Word;
+use crate::Memory;
 use std::fmt;
 
 #[derive(Copy, Clone, PartialEq, Debug)]
@@ -9,22 +10,84 @@ pub enum ParameterModes {
 	RelativeImmediate,
 }
 
-#[derive(Clone)]
+/// Largest number of parameters any instruction takes.
+pub const MAX_ARGUMENTS: usize = 3;
+
+/// Static description of an instruction in the ISA.
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub struct Instruction {
+	pub instruction: Instructions,
 	pub arguments_count: u8,
-	pub default_modes: Vec<ParameterModes>,
+	/// Mode of each parameter when the instruction word doesn't set one.
+	/// The parameter written to defaults to `Immediate`: its value is the
+	/// target address.
+	pub default_modes: &'static [ParameterModes],
+	/// Index of the parameter the instruction writes to, if any.
+	pub write_argument: Option<u8>,
 }
 
 impl Instruction {
-	pub fn new(arg_count: u8, default_mode: Vec<ParameterModes>) -> Instruction {
+	const fn new(
+		instruction: Instructions,
+		default_modes: &'static [ParameterModes],
+		write_argument: Option<u8>,
+	) -> Instruction {
 		Instruction {
-			arguments_count: arg_count,
-			default_modes: default_mode,
+			instruction,
+			arguments_count: default_modes.len() as u8,
+			default_modes,
+			write_argument,
 		}
 	}
 }
 
-#[derive(PartialEq, Hash, Eq, Debug)]
+const READ_READ_WRITE: &[ParameterModes] = &[
+	ParameterModes::Position,
+	ParameterModes::Position,
+	ParameterModes::Immediate,
+];
+const READ_READ: &[ParameterModes] = &[ParameterModes::Position, ParameterModes::Position];
+const READ_WRITE: &[ParameterModes] = &[ParameterModes::Position, ParameterModes::Immediate];
+const READ: &[ParameterModes] = &[ParameterModes::Position];
+const WRITE: &[ParameterModes] = &[ParameterModes::Immediate];
+
+/// Every instruction of the ISA, indexed by opcode.
+pub static INSTRUCTION_SET: [Option<Instruction>; 100] = instruction_set();
+
+const fn instruction_set() -> [Option<Instruction>; 100] {
+	let mut set = [None; 100];
+	set[1] = Some(Instruction::new(
+		Instructions::ADD,
+		READ_READ_WRITE,
+		Some(2),
+	));
+	set[2] = Some(Instruction::new(
+		Instructions::MUL,
+		READ_READ_WRITE,
+		Some(2),
+	));
+	set[3] = Some(Instruction::new(Instructions::IN, WRITE, Some(0)));
+	set[4] = Some(Instruction::new(Instructions::OUT, READ, None));
+	set[5] = Some(Instruction::new(Instructions::JMP, READ_READ, None));
+	set[6] = Some(Instruction::new(Instructions::JMPF, READ_READ, None));
+	set[7] = Some(Instruction::new(
+		Instructions::LESS,
+		READ_READ_WRITE,
+		Some(2),
+	));
+	set[8] = Some(Instruction::new(Instructions::EQ, READ_READ_WRITE, Some(2)));
+	set[9] = Some(Instruction::new(Instructions::ARB, READ, None));
+	set[10] = Some(Instruction::new(Instructions::MOV, READ_WRITE, Some(1)));
+	set[11] = Some(Instruction::new(
+		Instructions::GRT,
+		READ_READ_WRITE,
+		Some(2),
+	));
+	set[99] = Some(Instruction::new(Instructions::HLT, &[], None));
+	set
+}
+
+#[derive(Copy, Clone, PartialEq, Hash, Eq, Debug)]
 pub enum Instructions {
 	ADD,
 	MUL,
@@ -48,20 +111,25 @@ impl fmt::Display for Instructions {
 
 impl Instructions {
 	pub fn get_instruction_from_opc(instr_opc: usize) -> Result<Instructions, String> {
-		match instr_opc {
-			1 => Ok(Instructions::ADD),
-			2 => Ok(Instructions::MUL),
-			3 => Ok(Instructions::IN),
-			4 => Ok(Instructions::OUT),
-			5 => Ok(Instructions::JMP),
-			6 => Ok(Instructions::JMPF),
-			7 => Ok(Instructions::LESS),
-			8 => Ok(Instructions::EQ),
-			9 => Ok(Instructions::ARB),
-			10 => Ok(Instructions::MOV),
-			11 => Ok(Instructions::GRT),
-			99 => Ok(Instructions::HLT),
-			_ => Err(format!("Unknown instruction {}", instr_opc)),
+		match Instructions::get_instruction_data(instr_opc) {
+			Some(data) => Ok(data.instruction),
+			None => Err(format!("Unknown instruction {}", instr_opc)),
+		}
+	}
+
+	/// Looks up an opcode in `INSTRUCTION_SET`.
+	pub fn get_instruction_data(instr_opc: usize) -> Option<&'static Instruction> {
+		match INSTRUCTION_SET.get(instr_opc) {
+			Some(Some(data)) => Some(data),
+			_ => None,
+		}
+	}
+
+	/// Returns this instruction's entry in `INSTRUCTION_SET`.
+	pub fn data(&self) -> &'static Instruction {
+		match &INSTRUCTION_SET[self.get_instruction_opc()] {
+			Some(data) => data,
+			None => unreachable!("{} is missing from the instruction set", self),
 		}
 	}
 
@@ -99,90 +167,215 @@ impl Instructions {
 			_ => None,
 		}
 	}
+}
 
-	pub fn new() -> HashMap<Instructions, Instruction> {
-		let mut opcodes = HashMap::new();
-		opcodes.insert(
-			Instructions::ADD,
-			Instruction::new(
-				3,
-				vec![
-					ParameterModes::Position,
-					ParameterModes::Position,
-					ParameterModes::Immediate,
-				],
-			),
-		);
-		opcodes.insert(
-			Instructions::MUL,
-			Instruction::new(
-				3,
-				vec![
-					ParameterModes::Position,
-					ParameterModes::Position,
-					ParameterModes::Immediate,
-				],
-			),
-		);
-		opcodes.insert(
-			Instructions::IN,
-			Instruction::new(1, vec![ParameterModes::Immediate]),
-		);
-		opcodes.insert(
-			Instructions::OUT,
-			Instruction::new(1, vec![ParameterModes::Position]),
-		);
-		opcodes.insert(
-			Instructions::JMP,
-			Instruction::new(2, vec![ParameterModes::Position, ParameterModes::Position]),
-		);
-		opcodes.insert(
-			Instructions::JMPF,
-			Instruction::new(2, vec![ParameterModes::Position, ParameterModes::Position]),
-		);
-		opcodes.insert(
-			Instructions::LESS,
-			Instruction::new(
-				3,
-				vec![
-					ParameterModes::Position,
-					ParameterModes::Position,
-					ParameterModes::Immediate,
-				],
-			),
-		);
-		opcodes.insert(
-			Instructions::EQ,
-			Instruction::new(
-				3,
-				vec![
-					ParameterModes::Position,
-					ParameterModes::Position,
-					ParameterModes::Immediate,
-				],
-			),
-		);
-		opcodes.insert(
-			Instructions::ARB,
-			Instruction::new(1, vec![ParameterModes::Position]),
-		);
-		opcodes.insert(
-			Instructions::MOV,
-			Instruction::new(2, vec![ParameterModes::Position, ParameterModes::Immediate]),
-		);
-		opcodes.insert(
-			Instructions::GRT,
-			Instruction::new(
-				3,
-				vec![
-					ParameterModes::Position,
-					ParameterModes::Position,
-					ParameterModes::Immediate,
-				],
+/// A parameter together with the mode it is read in.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Operand<W = i64> {
+	pub mode: ParameterModes,
+	pub value: W,
+}
+
+impl<W> Operand<W> {
+	pub fn new(mode: ParameterModes, value: W) -> Operand<W> {
+		Operand { mode, value }
+	}
+}
+
+/// Instruction and parameter modes encoded in an instruction word.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Opcode {
+	pub instruction: Instructions,
+	modes: [ParameterModes; MAX_ARGUMENTS],
+}
+
+impl Opcode {
+	/// Mode of each of the instruction's parameters.
+	pub fn modes(&self) -> &[ParameterModes] {
+		&self.modes[..self.instruction.data().arguments_count as usize]
+	}
+}
+
+/// An instruction read back from memory.
+#[derive(Clone, PartialEq, Debug)]
+pub struct DecodedInstruction<W = i64> {
+	pub address: usize,
+	pub instruction: Instructions,
+	pub operands: Vec<Operand<W>>,
+}
+
+impl<W> DecodedInstruction<W> {
+	/// Number of cells the instruction takes, including the instruction word.
+	pub fn size(&self) -> usize {
+		1 + self.operands.len()
+	}
+}
+
+/// Formats the instruction in `icc_assembler` syntax: `$` marks immediate
+/// parameters, `#` position ones and relative parameters are bare numbers.
+impl<W: fmt::Display> fmt::Display for DecodedInstruction<W> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.instruction)?;
+		let write_argument = self.instruction.data().write_argument;
+		for (i, operand) in self.operands.iter().enumerate() {
+			let separator = if i == 0 { " " } else { ", " };
+			let prefix = match (operand.mode, write_argument == Some(i as u8)) {
+				(ParameterModes::Position, _) | (ParameterModes::Immediate, true) => "#",
+				(ParameterModes::Immediate, false) => "$",
+				(ParameterModes::Relative, _) | (ParameterModes::RelativeImmediate, _) => "",
+			};
+			write!(f, "{}{}{}", separator, prefix, operand.value)?;
+		}
+		Ok(())
+	}
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum DecodeError<W = i64> {
+	/// The instruction word doesn't encode a known instruction.
+	UnknownOpcode(W),
+	/// A mode digit is not 0, 1 or 2, or there are more mode digits than the
+	/// instruction has parameters.
+	InvalidMode(i64),
+	/// The parameter the instruction writes to is in immediate mode.
+	WriteToImmediate,
+}
+
+impl<W: fmt::Display> fmt::Display for DecodeError<W> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			DecodeError::UnknownOpcode(word) => write!(f, "Unknown instruction {}", word),
+			DecodeError::InvalidMode(mode) => write!(f, "Invalid parameter mode {}", mode),
+			DecodeError::WriteToImmediate => write!(f, "Write to an immediate mode parameter"),
+		}
+	}
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum EncodeError {
+	/// The instruction takes `expected` parameters but `found` were given.
+	WrongOperandCount { expected: u8, found: usize },
+	/// The mode can't be used for this parameter: `RelativeImmediate` is only
+	/// valid for the parameter an instruction writes to, which in turn only
+	/// accepts `Immediate` and `RelativeImmediate`.
+	InvalidMode {
+		argument: usize,
+		mode: ParameterModes,
+	},
+}
+
+impl fmt::Display for EncodeError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			EncodeError::WrongOperandCount { expected, found } => write!(
+				f,
+				"Wrong number of arguments expected {} found {}",
+				expected, found
 			),
-		);
-		opcodes.insert(Instructions::HLT, Instruction::new(0, vec![]));
+			EncodeError::InvalidMode { argument, mode } => {
+				write!(f, "Mode {:?} is not valid for argument {}", mode, argument)
+			}
+		}
+	}
+}
 
-		opcodes
+/// Decodes the instruction and parameter modes of an instruction word.
+pub fn decode_opcode<W: Word>(word: &W) -> Result<Opcode, DecodeError<W>> {
+	let opc = match word.to_i64() {
+		Some(opc) if opc >= 0 => opc,
+		_ => return Err(DecodeError::UnknownOpcode(word.clone())),
+	};
+	let data = match Instructions::get_instruction_data((opc % 100) as usize) {
+		Some(data) => data,
+		None => return Err(DecodeError::UnknownOpcode(word.clone())),
+	};
+	let argument_count = data.arguments_count as usize;
+	let mut modes = [ParameterModes::Position; MAX_ARGUMENTS];
+	modes[..argument_count].copy_from_slice(data.default_modes);
+	let mut mode_digits = opc / 100;
+	let mut i = 0;
+	while mode_digits != 0 {
+		let digit = mode_digits % 10;
+		if i >= argument_count {
+			return Err(DecodeError::InvalidMode(digit));
+		}
+		let is_write_target = data.write_argument == Some(i as u8);
+		modes[i] = match digit {
+			0 => data.default_modes[i],
+			1 if is_write_target => return Err(DecodeError::WriteToImmediate),
+			1 => ParameterModes::Immediate,
+			2 if is_write_target => ParameterModes::RelativeImmediate,
+			2 => ParameterModes::Relative,
+			_ => return Err(DecodeError::InvalidMode(digit)),
+		};
+		mode_digits /= 10;
+		i += 1;
 	}
+	Ok(Opcode {
+		instruction: data.instruction,
+		modes,
+	})
+}
+
+/// Decodes the instruction stored at `address`.
+pub fn decode<W: Word>(
+	memory: &Memory<W>,
+	address: usize,
+) -> Result<DecodedInstruction<W>, DecodeError<W>> {
+	let opcode = decode_opcode(&memory.read(address))?;
+	let operands = opcode
+		.modes()
+		.iter()
+		.enumerate()
+		.map(|(i, mode)| Operand::new(*mode, memory.read(address + 1 + i)))
+		.collect();
+	Ok(DecodedInstruction {
+		address,
+		instruction: opcode.instruction,
+		operands,
+	})
+}
+
+/// Encodes an instruction and its parameter modes into an instruction word.
+pub fn encode_opcode(
+	instruction: Instructions,
+	modes: &[ParameterModes],
+) -> Result<i64, EncodeError> {
+	let data = instruction.data();
+	if modes.len() != data.arguments_count as usize {
+		return Err(EncodeError::WrongOperandCount {
+			expected: data.arguments_count,
+			found: modes.len(),
+		});
+	}
+	let mut word = instruction.get_instruction_opc() as i64;
+	let mut digit_value = 100;
+	for (argument, mode) in modes.iter().enumerate() {
+		let is_write_target = data.write_argument == Some(argument as u8);
+		let digit = match (mode, is_write_target) {
+			(ParameterModes::Position, false) | (ParameterModes::Immediate, true) => 0,
+			(ParameterModes::Immediate, false) => 1,
+			(ParameterModes::Relative, false) | (ParameterModes::RelativeImmediate, true) => 2,
+			_ => {
+				return Err(EncodeError::InvalidMode {
+					argument,
+					mode: *mode,
+				})
+			}
+		};
+		word += digit * digit_value;
+		digit_value *= 10;
+	}
+	Ok(word)
+}
+
+/// Encodes an instruction into the cells it takes in memory.
+pub fn encode<W: Word>(
+	instruction: Instructions,
+	operands: &[Operand<W>],
+) -> Result<Vec<W>, EncodeError> {
+	let modes: Vec<ParameterModes> = operands.iter().map(|operand| operand.mode).collect();
+	let mut cells = vec![W::from_i64(encode_opcode(instruction, &modes)?)];
+	cells.extend(operands.iter().map(|operand| operand.value.clone()));
+	Ok(cells)
 }
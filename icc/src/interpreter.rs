@@ -1,193 +1,1267 @@
-use crate::instructions::{Instruction, Instructions, ParameterModes};
+use crate::instructions::{
+	decode_opcode, DecodeError, Instructions, Opcode, ParameterModes, MAX_ARGUMENTS,
+};
+use crate::state::MachineState;
+use crate::trace::Tracer;
+use crate::word::Word;
 use crate::Memory;
-use std::collections::HashMap;
+use std::array;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
+use std::time::{Duration, Instant};
 
-type OutputHandle = fn(i64);
-type InputHandle = fn() -> i64;
+pub type OutputHandle<W = i64> = Box<dyn FnMut(W) + Send>;
+pub type InputHandle<W = i64> = Box<dyn FnMut() -> W + Send>;
 
-pub struct IntCodeProgram {
+/// Error raised when a program can't continue executing. `ip` is always the
+/// address of the instruction that failed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum VmError<W = i64> {
+	/// The instruction word doesn't encode a known instruction.
+	UnknownOpcode { opcode: W, ip: usize },
+	/// A parameter mode digit is not 0, 1 or 2, or there are more mode
+	/// digits than the instruction has parameters.
+	InvalidMode { mode: i64, ip: usize },
+	/// A read, write or jump resolved to a negative address.
+	NegativeAddress { addr: W, ip: usize },
+	/// A read, write or jump resolved to an address that doesn't fit in
+	/// memory, or a write went past the memory's `max_size`.
+	AddressOutOfRange { addr: W, ip: usize },
+	/// The parameter an instruction writes to is in immediate mode.
+	WriteToImmediate { ip: usize },
+	/// `run` reached an `IN` with no queued input and no input handle.
+	MissingInput { ip: usize },
+	/// `run` ran out of the budget set with `set_budget`.
+	BudgetExhausted { ip: usize },
+	/// An arithmetic result overflowed under `OverflowPolicy::Checked`.
+	Overflow { ip: usize },
+}
+
+impl<W: Word> fmt::Display for VmError<W> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			VmError::UnknownOpcode { opcode, ip } => {
+				write!(f, "Unknown instruction {} at {}", opcode, ip)
+			}
+			VmError::InvalidMode { mode, ip } => {
+				write!(f, "Invalid parameter mode {} at {}", mode, ip)
+			}
+			VmError::NegativeAddress { addr, ip } => {
+				write!(f, "Negative address {} accessed at {}", addr, ip)
+			}
+			VmError::AddressOutOfRange { addr, ip } => {
+				write!(f, "Address {} accessed at {} is out of range", addr, ip)
+			}
+			VmError::WriteToImmediate { ip } => {
+				write!(f, "Write to an immediate mode parameter at {}", ip)
+			}
+			VmError::MissingInput { ip } => write!(f, "No input available for IN at {}", ip),
+			VmError::BudgetExhausted { ip } => write!(f, "Execution budget exhausted at {}", ip),
+			VmError::Overflow { ip } => write!(f, "Arithmetic overflow at {}", ip),
+		}
+	}
+}
+
+impl<W: Word> error::Error for VmError<W> {}
+
+impl<W> VmError<W> {
+	/// Converts an error from decoding the instruction at `ip`.
+	pub fn from_decode_error(error: DecodeError<W>, ip: usize) -> VmError<W> {
+		match error {
+			DecodeError::UnknownOpcode(opcode) => VmError::UnknownOpcode { opcode, ip },
+			DecodeError::InvalidMode(mode) => VmError::InvalidMode { mode, ip },
+			DecodeError::WriteToImmediate => VmError::WriteToImmediate { ip },
+		}
+	}
+}
+
+/// Description of a single executed instruction, returned by
+/// `IntCodeProgram::step`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct StepInfo<W = i64> {
+	/// Address of the instruction.
+	pub address: usize,
+	pub instruction: Instructions,
+	modes: [ParameterModes; MAX_ARGUMENTS],
+	operands: [W; MAX_ARGUMENTS],
+	values: [W; MAX_ARGUMENTS],
+	reads: [Option<usize>; MAX_ARGUMENTS],
+	argument_count: usize,
+	/// Address written by the instruction, if any.
+	pub write_address: Option<usize>,
+	/// Value written to `write_address`.
+	pub written_value: Option<W>,
+	/// Instruction pointer after the step.
+	pub instruction_pointer: usize,
+	/// Event produced by the instruction, if any.
+	pub event: Option<ExecutionEvent<W>>,
+}
+
+impl<W> StepInfo<W> {
+	/// Resolved mode of each parameter.
+	pub fn modes(&self) -> &[ParameterModes] {
+		&self.modes[..self.argument_count]
+	}
+
+	/// Raw parameter values as stored after the instruction word.
+	pub fn operands(&self) -> &[W] {
+		&self.operands[..self.argument_count]
+	}
+
+	/// Parameter values after applying their modes. For the parameter an
+	/// instruction writes to, this is the target address.
+	pub fn values(&self) -> &[W] {
+		&self.values[..self.argument_count]
+	}
+
+	/// Addresses read by position and relative mode parameters.
+	pub fn read_addresses(&self) -> impl Iterator<Item = usize> + '_ {
+		self.reads[..self.argument_count].iter().flatten().copied()
+	}
+}
+
+/// Instructions below this address have their decoded opcode cached.
+const DECODE_CACHE_LIMIT: usize = 1 << 16;
+
+/// Decoded opcodes keyed by address, so hot loops decode each instruction
+/// word only once. Only the instruction word itself is cached; parameters are
+/// always read from memory, so a write only needs to invalidate the cell it
+/// changed.
+#[derive(Clone, Default)]
+struct DecodeCache {
+	entries: Vec<Option<Opcode>>,
+}
+
+impl DecodeCache {
+	fn get(&self, address: usize) -> Option<Opcode> {
+		self.entries.get(address).copied().flatten()
+	}
+
+	fn insert(&mut self, address: usize, opcode: Opcode) {
+		if address >= DECODE_CACHE_LIMIT {
+			return;
+		}
+		if address >= self.entries.len() {
+			self.entries.resize(address + 1, None);
+		}
+		self.entries[address] = Some(opcode);
+	}
+
+	fn invalidate(&mut self, address: usize) {
+		if let Some(entry) = self.entries.get_mut(address) {
+			*entry = None;
+		}
+	}
+
+	fn clear(&mut self) {
+		self.entries.clear();
+	}
+}
+
+/// Reason why `IntCodeProgram::run_until_event` returned control to the host.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ExecutionEvent<W = i64> {
+	/// An `IN` instruction found no queued input and there is no input handle.
+	/// The instruction pointer is left on the `IN`, so pushing a value with
+	/// `push_input` and calling `run_until_event` again resumes it.
+	NeedsInput,
+	/// An `OUT` instruction produced a value.
+	Output(W),
+	/// The program reached `HLT`.
+	Halted,
+	/// The program ran out of the `Budget` set with `set_budget`. Execution
+	/// stopped before an instruction and resumes normally once the budget
+	/// is raised or removed.
+	BudgetExhausted,
+	/// A breakpoint matched before the instruction at `ip` executed. The
+	/// instruction hasn't run yet; resuming executes it without stopping at
+	/// any breakpoint again.
+	Breakpoint { id: BreakpointId, ip: usize },
+	/// The instruction at `ip` accessed the watched `address`. The
+	/// instruction has already run.
+	Watchpoint {
+		id: BreakpointId,
+		ip: usize,
+		address: usize,
+		access: Access,
+	},
+}
+
+/// Identifies a breakpoint or watchpoint added to a program.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct BreakpointId(u32);
+
+impl fmt::Display for BreakpointId {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.0)
+	}
+}
+
+/// Machine state a breakpoint can wait for.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Condition<W = i64> {
+	/// The relative base equals the value.
+	RelativeBase(W),
+	/// The memory cell at `address` holds `value`.
+	Memory { address: usize, value: W },
+}
+
+impl<W: Word> Condition<W> {
+	fn holds(&self, program: &IntCodeProgram<W>) -> bool {
+		match self {
+			Condition::RelativeBase(value) => program.base == *value,
+			Condition::Memory { address, value } => program.memory.read(*address) == *value,
+		}
+	}
+}
+
+/// Stops `run_until_event` before an instruction executes. A breakpoint
+/// with both an address and a condition only stops at that address while
+/// the condition holds; one with only a condition is checked before every
+/// instruction.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Breakpoint<W = i64> {
+	pub address: Option<usize>,
+	pub condition: Option<Condition<W>>,
+}
+
+impl<W: Word> Breakpoint<W> {
+	fn matches(&self, program: &IntCodeProgram<W>) -> bool {
+		self.address
+			.is_none_or(|address| address == program.instruction_pointer)
+			&& self
+				.condition
+				.as_ref()
+				.is_none_or(|condition| condition.holds(program))
+	}
+}
+
+impl<W> Breakpoint<W> {
+	/// Stops before the instruction at `address`.
+	pub fn at(address: usize) -> Breakpoint<W> {
+		Breakpoint {
+			address: Some(address),
+			condition: None,
+		}
+	}
+
+	/// Stops before any instruction once `condition` holds.
+	pub fn when(condition: Condition<W>) -> Breakpoint<W> {
+		Breakpoint {
+			address: None,
+			condition: Some(condition),
+		}
+	}
+
+	/// Stops before the instruction at `address` if `condition` holds.
+	pub fn at_if(address: usize, condition: Condition<W>) -> Breakpoint<W> {
+		Breakpoint {
+			address: Some(address),
+			condition: Some(condition),
+		}
+	}
+}
+
+/// Kind of memory access an instruction performed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Access {
+	Read,
+	Write,
+}
+
+/// Stops `run_until_event` after an instruction reads or writes a cell.
+/// Only parameter reads and instruction writes count; fetching instructions
+/// and their parameters doesn't.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Watchpoint {
+	pub address: usize,
+	pub on_read: bool,
+	pub on_write: bool,
+}
+
+impl Watchpoint {
+	pub fn read(address: usize) -> Watchpoint {
+		Watchpoint {
+			address,
+			on_read: true,
+			on_write: false,
+		}
+	}
+
+	pub fn write(address: usize) -> Watchpoint {
+		Watchpoint {
+			address,
+			on_read: false,
+			on_write: true,
+		}
+	}
+
+	/// Watches both reads and writes.
+	pub fn access(address: usize) -> Watchpoint {
+		Watchpoint {
+			address,
+			on_read: true,
+			on_write: true,
+		}
+	}
+}
+
+/// Limits on how long `run_until_event` may execute before returning
+/// `ExecutionEvent::BudgetExhausted`. A `None` limit is never exhausted.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Budget {
+	/// Number of instructions the program may execute.
+	pub instructions: Option<u64>,
+	/// Wall-clock time the program may spend inside `run_until_event`.
+	pub time: Option<Duration>,
+}
+
+impl Budget {
+	pub fn instructions(count: u64) -> Budget {
+		Budget {
+			instructions: Some(count),
+			time: None,
+		}
+	}
+
+	pub fn time(limit: Duration) -> Budget {
+		Budget {
+			instructions: None,
+			time: Some(limit),
+		}
+	}
+}
+
+/// What arithmetic does when a result doesn't fit in the word type. Applies to
+/// `ADD`, `MUL`, `ARB` and relative mode address computation.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum OverflowPolicy {
+	/// Stop with `VmError::Overflow`. This is the default.
+	#[default]
+	Checked,
+	/// Wrap around using two's complement arithmetic.
+	Wrapping,
+	/// Clamp to the smallest or largest value of the word type.
+	Saturating,
+}
+
+impl OverflowPolicy {
+	fn add<W: Word>(self, a: &W, b: &W, ip: usize) -> Result<W, VmError<W>> {
+		match self {
+			OverflowPolicy::Checked => a.checked_add(b).ok_or(VmError::Overflow { ip }),
+			OverflowPolicy::Wrapping => Ok(a.wrapping_add(b)),
+			OverflowPolicy::Saturating => Ok(a.saturating_add(b)),
+		}
+	}
+
+	fn mul<W: Word>(self, a: &W, b: &W, ip: usize) -> Result<W, VmError<W>> {
+		match self {
+			OverflowPolicy::Checked => a.checked_mul(b).ok_or(VmError::Overflow { ip }),
+			OverflowPolicy::Wrapping => Ok(a.wrapping_mul(b)),
+			OverflowPolicy::Saturating => Ok(a.saturating_mul(b)),
+		}
+	}
+}
+
+/// Largest number of steps in each segment of the history journal.
+const HISTORY_SEGMENT: usize = 4096;
+
+/// What a step changed, so it can be undone.
+#[derive(Clone)]
+struct UndoRecord<W> {
+	instruction_pointer: usize,
+	/// Relative base before an `ARB`.
+	base: Option<W>,
+	/// Address written and the value it held before.
+	write: Option<(usize, W)>,
+	consumed_input: bool,
+	output: bool,
+}
+
+/// State of the program before the first step of a history segment.
+#[derive(Clone)]
+struct Checkpoint<W> {
+	instruction_pointer: usize,
+	base: W,
+	/// Shares pages with the program's memory until either writes to them.
+	memory: Memory<W>,
+	next_input: usize,
+	output_len: usize,
+	executed_instructions: u64,
+}
+
+/// Steps journaled since a checkpoint.
+#[derive(Clone)]
+struct Segment<W> {
+	checkpoint: Checkpoint<W>,
+	records: Vec<UndoRecord<W>>,
+}
+
+/// Undo journal of the most recent steps. Records are kept in segments that
+/// start with a checkpoint, and whole segments are dropped once the journal
+/// holds more than `capacity` steps, so memory stays bounded however long
+/// the program runs. Stepping back over a whole segment restores its
+/// checkpoint instead of undoing every step in it.
+#[derive(Clone)]
+struct History<W> {
+	capacity: usize,
+	segment_len: usize,
+	segments: VecDeque<Segment<W>>,
+	len: usize,
+}
+
+impl<W> History<W> {
+	fn new(capacity: usize) -> History<W> {
+		History {
+			capacity,
+			segment_len: capacity.clamp(1, HISTORY_SEGMENT),
+			segments: VecDeque::new(),
+			len: 0,
+		}
+	}
+
+	/// Whether the next record starts a segment, which needs a checkpoint.
+	fn needs_checkpoint(&self) -> bool {
+		self.segments
+			.back()
+			.is_none_or(|segment| segment.records.len() >= self.segment_len)
+	}
+
+	/// Journals a step. `checkpoint` must be given when `needs_checkpoint`.
+	fn push(&mut self, record: UndoRecord<W>, checkpoint: Option<Checkpoint<W>>) {
+		match checkpoint {
+			Some(checkpoint) => {
+				let mut records = Vec::with_capacity(self.segment_len);
+				records.push(record);
+				self.segments.push_back(Segment {
+					checkpoint,
+					records,
+				});
+			}
+			None => self
+				.segments
+				.back_mut()
+				.expect("history segment without a checkpoint")
+				.records
+				.push(record),
+		}
+		self.len += 1;
+		while let Some(oldest) = self.segments.front() {
+			if self.len - oldest.records.len() < self.capacity {
+				break;
+			}
+			self.len -= oldest.records.len();
+			self.segments.pop_front();
+		}
+	}
+
+	fn pop(&mut self) -> Option<UndoRecord<W>> {
+		let segment = self.segments.back_mut()?;
+		let record = segment.records.pop();
+		if segment.records.is_empty() {
+			self.segments.pop_back();
+		}
+		self.len -= 1;
+		record
+	}
+
+	/// Removes the newest segment if it holds at most `steps` steps, and
+	/// returns how many it held with its checkpoint.
+	fn pop_segment(&mut self, steps: usize) -> Option<(usize, Checkpoint<W>)> {
+		if self.segments.back()?.records.len() > steps {
+			return None;
+		}
+		let segment = self.segments.pop_back()?;
+		self.len -= segment.records.len();
+		Some((segment.records.len(), segment.checkpoint))
+	}
+}
+
+/// How many instructions are executed between wall-clock budget checks.
+const TIME_CHECK_INTERVAL: u64 = 1024;
+
+pub struct IntCodeProgram<W = i64> {
 	instruction_pointer: usize,
-	memory: Memory,
-	base: i64,
-	opcodes: HashMap<Instructions, Instruction>,
-	input: Vec<i64>,
+	memory: Memory<W>,
+	base: W,
+	input: Vec<W>,
 	next_input: usize,
-	output: Vec<i64>,
-	output_handle: OutputHandle,
-	input_handle: InputHandle,
-}
-
-impl IntCodeProgram {
-	pub fn new(
-		p_memory: Memory,
-		input: Vec<i64>,
-		output_handle: OutputHandle,
-		input_handle: InputHandle,
-	) -> IntCodeProgram {
-		let program = IntCodeProgram {
+	output: Vec<W>,
+	output_handle: Option<OutputHandle<W>>,
+	input_handle: Option<InputHandle<W>>,
+	executed_instructions: u64,
+	budget: Budget,
+	budget_start: u64,
+	budget_elapsed: Duration,
+	overflow_policy: OverflowPolicy,
+	decode_cache: DecodeCache,
+	breakpoints: Vec<(BreakpointId, Breakpoint<W>)>,
+	watchpoints: Vec<(BreakpointId, Watchpoint)>,
+	next_breakpoint_id: u32,
+	/// Address of the instruction a breakpoint stopped at, which the next
+	/// run executes without checking breakpoints.
+	resume_address: Option<usize>,
+	/// Watchpoint hit by an instruction that also produced output, returned
+	/// by the next `run_until_event`.
+	pending_event: Option<ExecutionEvent<W>>,
+	/// Whether the last step was a `HLT`, which is only traced the first
+	/// time the program reaches it.
+	halted: bool,
+	tracer: Option<Box<dyn Tracer<W> + Send>>,
+	history: Option<History<W>>,
+}
+
+impl<W: Word> IntCodeProgram<W> {
+	/// Creates a program over `p_memory`.
+	///
+	/// `input` is consumed first by `IN` instructions; once it runs out,
+	/// `input_handle` is called for every further value. Every value written
+	/// by `OUT` is passed to `output_handle`. Both handles may capture state,
+	/// which must be `Send` so the program can move to another thread.
+	pub fn new<O, I>(
+		p_memory: Memory<W>,
+		input: Vec<W>,
+		output_handle: O,
+		input_handle: I,
+	) -> IntCodeProgram<W>
+	where
+		O: FnMut(W) + Send + 'static,
+		I: FnMut() -> W + Send + 'static,
+	{
+		let mut program = IntCodeProgram::from_memory(p_memory, input);
+		program.output_handle = Some(Box::new(output_handle));
+		program.input_handle = Some(Box::new(input_handle));
+		program
+	}
+
+	/// Creates a program without input or output handles, meant to be driven
+	/// with `run_until_event` and `push_input`.
+	pub fn from_memory(p_memory: Memory<W>, input: Vec<W>) -> IntCodeProgram<W> {
+		IntCodeProgram {
 			instruction_pointer: 0,
 			memory: p_memory,
-			opcodes: Instructions::new(),
-			base: 0,
-			input: input,
+			base: W::zero(),
+			input,
 			next_input: 0,
 			output: Vec::new(),
-			output_handle: output_handle,
-			input_handle: input_handle,
-		};
+			output_handle: None,
+			input_handle: None,
+			executed_instructions: 0,
+			budget: Budget::default(),
+			budget_start: 0,
+			budget_elapsed: Duration::default(),
+			overflow_policy: OverflowPolicy::default(),
+			decode_cache: DecodeCache::default(),
+			breakpoints: Vec::new(),
+			watchpoints: Vec::new(),
+			next_breakpoint_id: 0,
+			resume_address: None,
+			pending_event: None,
+			halted: false,
+			tracer: None,
+			history: None,
+		}
+	}
+
+	/// Creates a program, without handles, that resumes from a saved state.
+	pub fn from_state(state: MachineState<W>) -> IntCodeProgram<W> {
+		let mut program = IntCodeProgram::from_memory(state.memory, state.input);
+		program.instruction_pointer = state.instruction_pointer;
+		program.base = state.relative_base;
+		program.next_input = state.consumed_input;
+		program.output = state.output;
+		program.executed_instructions = state.instructions_executed;
+		program.budget_start = state.instructions_executed;
+		program.overflow_policy = state.overflow_policy;
 		program
 	}
 
-	fn parse_instruction(
-		&self,
-		instruction_opc: i64,
-	) -> (Instructions, &Instruction, Vec<ParameterModes>) {
-		let instruction = instruction_opc % 100;
-		let instruction = Instructions::get_instruction_from_opc(instruction as usize);
-		let instruction = match instruction {
-			Ok(o) => o,
-			Err(e) => panic!("{} at {}", e, self.instruction_pointer),
+	/// Creates an independent copy of the program in its current state.
+	///
+	/// The copy shares memory pages with this program until either writes
+	/// to them, so forking costs the same however large the memory is. It
+	/// keeps the breakpoints, budget and overflow policy, but has no handles
+	/// or tracer and starts with an empty history.
+	pub fn fork(&self) -> IntCodeProgram<W> {
+		IntCodeProgram {
+			instruction_pointer: self.instruction_pointer,
+			memory: self.memory.clone(),
+			base: self.base.clone(),
+			input: self.input.clone(),
+			next_input: self.next_input,
+			output: self.output.clone(),
+			output_handle: None,
+			input_handle: None,
+			executed_instructions: self.executed_instructions,
+			budget: self.budget,
+			budget_start: self.budget_start,
+			budget_elapsed: self.budget_elapsed,
+			overflow_policy: self.overflow_policy,
+			decode_cache: DecodeCache::default(),
+			breakpoints: self.breakpoints.clone(),
+			watchpoints: self.watchpoints.clone(),
+			next_breakpoint_id: self.next_breakpoint_id,
+			resume_address: self.resume_address,
+			pending_event: self.pending_event.clone(),
+			halted: self.halted,
+			tracer: None,
+			history: self
+				.history
+				.as_ref()
+				.map(|history| History::new(history.capacity)),
+		}
+	}
+
+	/// Takes a snapshot of the program that `from_state` can resume.
+	pub fn save_state(&self) -> MachineState<W> {
+		MachineState {
+			memory: self.memory.clone(),
+			instruction_pointer: self.instruction_pointer,
+			relative_base: self.base.clone(),
+			input: self.input.clone(),
+			consumed_input: self.next_input,
+			output: self.output.clone(),
+			instructions_executed: self.executed_instructions,
+			overflow_policy: self.overflow_policy,
+		}
+	}
+
+	/// Queues a value to be read by a later `IN` instruction.
+	pub fn push_input(&mut self, value: W) {
+		self.input.push(value);
+	}
+
+	/// Address of the next instruction to execute.
+	pub fn instruction_pointer(&self) -> usize {
+		self.instruction_pointer
+	}
+
+	pub fn set_instruction_pointer(&mut self, address: usize) {
+		self.instruction_pointer = address;
+		self.halted = false;
+	}
+
+	/// Current relative base used by relative mode parameters.
+	pub fn relative_base(&self) -> &W {
+		&self.base
+	}
+
+	pub fn set_relative_base(&mut self, base: W) {
+		self.base = base;
+	}
+
+	pub fn memory(&self) -> &Memory<W> {
+		&self.memory
+	}
+
+	/// Gives unrestricted access to the program's memory. This drops every
+	/// cached instruction decode, since any cell may change.
+	pub fn memory_mut(&mut self) -> &mut Memory<W> {
+		self.decode_cache.clear();
+		self.halted = false;
+		&mut self.memory
+	}
+
+	/// Queued input values that no `IN` instruction has consumed yet.
+	pub fn pending_input(&self) -> &[W] {
+		&self.input[self.next_input..]
+	}
+
+	/// Drops every queued input value that hasn't been consumed yet.
+	pub fn clear_pending_input(&mut self) {
+		self.input.truncate(self.next_input);
+	}
+
+	/// Replaces the execution budget. Usage is counted from this call, so
+	/// setting a budget again after `ExecutionEvent::BudgetExhausted` grants
+	/// the program a fresh allowance.
+	pub fn set_budget(&mut self, budget: Budget) {
+		self.budget = budget;
+		self.budget_start = self.executed_instructions;
+		self.budget_elapsed = Duration::default();
+	}
+
+	pub fn budget(&self) -> Budget {
+		self.budget
+	}
+
+	pub fn set_overflow_policy(&mut self, policy: OverflowPolicy) {
+		self.overflow_policy = policy;
+	}
+
+	pub fn overflow_policy(&self) -> OverflowPolicy {
+		self.overflow_policy
+	}
+
+	/// Total number of instructions executed by this program.
+	pub fn instructions_executed(&self) -> u64 {
+		self.executed_instructions
+	}
+
+	fn next_breakpoint_id(&mut self) -> BreakpointId {
+		let id = BreakpointId(self.next_breakpoint_id);
+		self.next_breakpoint_id += 1;
+		id
+	}
+
+	pub fn add_breakpoint(&mut self, breakpoint: Breakpoint<W>) -> BreakpointId {
+		let id = self.next_breakpoint_id();
+		self.breakpoints.push((id, breakpoint));
+		id
+	}
+
+	pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> BreakpointId {
+		let id = self.next_breakpoint_id();
+		self.watchpoints.push((id, watchpoint));
+		id
+	}
+
+	/// Removes a breakpoint or watchpoint. Returns `false` if `id` wasn't set.
+	pub fn remove_breakpoint(&mut self, id: BreakpointId) -> bool {
+		let count = self.breakpoints.len() + self.watchpoints.len();
+		self.breakpoints.retain(|(other, _)| *other != id);
+		self.watchpoints.retain(|(other, _)| *other != id);
+		count != self.breakpoints.len() + self.watchpoints.len()
+	}
+
+	/// Removes every breakpoint and watchpoint.
+	pub fn clear_breakpoints(&mut self) {
+		self.breakpoints.clear();
+		self.watchpoints.clear();
+	}
+
+	pub fn breakpoints(&self) -> impl Iterator<Item = (BreakpointId, &Breakpoint<W>)> {
+		self.breakpoints
+			.iter()
+			.map(|(id, breakpoint)| (*id, breakpoint))
+	}
+
+	pub fn watchpoints(&self) -> impl Iterator<Item = (BreakpointId, &Watchpoint)> {
+		self.watchpoints
+			.iter()
+			.map(|(id, watchpoint)| (*id, watchpoint))
+	}
+
+	fn hit_breakpoint(&self) -> Option<BreakpointId> {
+		self.breakpoints
+			.iter()
+			.find(|(_, breakpoint)| breakpoint.matches(self))
+			.map(|(id, _)| *id)
+	}
+
+	fn hit_watchpoint(&self, step: &StepInfo<W>) -> Option<ExecutionEvent<W>> {
+		let mut accesses = step
+			.read_addresses()
+			.map(|address| (address, Access::Read))
+			.chain(step.write_address.map(|address| (address, Access::Write)));
+		accesses.find_map(|(address, access)| {
+			self.watchpoints
+				.iter()
+				.find(|(_, watchpoint)| {
+					watchpoint.address == address
+						&& match access {
+							Access::Read => watchpoint.on_read,
+							Access::Write => watchpoint.on_write,
+						}
+				})
+				.map(|(id, _)| ExecutionEvent::Watchpoint {
+					id: *id,
+					ip: step.address,
+					address,
+					access,
+				})
+		})
+	}
+
+	/// Records every instruction executed from now on with `tracer`.
+	pub fn set_tracer<T: Tracer<W> + Send + 'static>(&mut self, tracer: T) {
+		self.tracer = Some(Box::new(tracer));
+	}
+
+	/// Stops tracing and returns the tracer.
+	pub fn take_tracer(&mut self) -> Option<Box<dyn Tracer<W> + Send>> {
+		self.tracer.take()
+	}
+
+	/// Keeps an undo journal of at least the last `steps` executed
+	/// instructions, so they can be reverted with `step_back` and
+	/// `run_back_to`. `None` turns the journal off and drops it.
+	///
+	/// A checkpoint of the machine is taken every few thousand steps; memory
+	/// pages are shared with it until written, so each costs about the pages
+	/// the program writes before the next one.
+	///
+	/// Only the program's own execution is journaled: changes made through
+	/// `memory_mut` or the setters aren't reliably undone, and output already
+	/// removed with `take_output` isn't restored.
+	pub fn set_history(&mut self, steps: Option<usize>) {
+		self.history = steps.map(History::new);
+	}
+
+	/// Number of steps that can currently be undone.
+	pub fn history_len(&self) -> usize {
+		self.history.as_ref().map_or(0, |history| history.len)
+	}
+
+	fn undo(&mut self) -> bool {
+		let record = match self.history.as_mut().and_then(History::pop) {
+			Some(record) => record,
+			None => return false,
 		};
-		let instruction_data = &self.opcodes[&instruction];
-		let mut modes = instruction_opc / 100;
-		let mut modes_arr = instruction_data.default_modes.clone();
-		let mut i = 0;
-
-		while modes != 0 {
-			match modes % 10 {
-				0 => modes_arr[i] = ParameterModes::Position,
-				1 => modes_arr[i] = ParameterModes::Immediate,
-				2 => {
-					if modes_arr[i] == ParameterModes::Immediate {
-						modes_arr[i] = ParameterModes::RelativeImmediate;
-					} else {
-						modes_arr[i] = ParameterModes::Relative;
-					}
-				}
-				_ => panic!("Unknown mode"),
+		self.instruction_pointer = record.instruction_pointer;
+		if let Some(base) = record.base {
+			self.base = base;
+		}
+		if let Some((address, value)) = record.write {
+			self.memory.write(address, value);
+			self.decode_cache.invalidate(address);
+		}
+		if record.consumed_input {
+			self.next_input -= 1;
+		}
+		if record.output {
+			self.output.pop();
+		}
+		self.executed_instructions -= 1;
+		self.halted = false;
+		true
+	}
+
+	/// Restores the program to a checkpoint taken by the history.
+	fn restore(&mut self, checkpoint: Checkpoint<W>) {
+		self.instruction_pointer = checkpoint.instruction_pointer;
+		self.base = checkpoint.base;
+		self.memory = checkpoint.memory;
+		self.next_input = checkpoint.next_input;
+		self.output.truncate(checkpoint.output_len);
+		self.executed_instructions = checkpoint.executed_instructions;
+		self.decode_cache = DecodeCache::default();
+		self.halted = false;
+	}
+
+	fn checkpoint(&self) -> Checkpoint<W> {
+		Checkpoint {
+			instruction_pointer: self.instruction_pointer,
+			base: self.base.clone(),
+			memory: self.memory.clone(),
+			next_input: self.next_input,
+			output_len: self.output.len(),
+			executed_instructions: self.executed_instructions,
+		}
+	}
+
+	/// Reverts up to `count` steps and returns how many were reverted, which
+	/// is less than `count` when the journal runs out.
+	pub fn step_back(&mut self, count: usize) -> usize {
+		self.resume_address = None;
+		self.pending_event = None;
+		let mut reverted = 0;
+		while reverted < count {
+			let segment = self
+				.history
+				.as_mut()
+				.and_then(|history| history.pop_segment(count - reverted));
+			if let Some((steps, checkpoint)) = segment {
+				self.restore(checkpoint);
+				reverted += steps;
+			} else if self.undo() {
+				reverted += 1;
+			} else {
+				break;
+			}
+		}
+		reverted
+	}
+
+	/// Reverts steps until the state matches `breakpoint`. At least one step
+	/// is reverted. Returns `false`, with every journaled step reverted, if
+	/// the breakpoint never matched.
+	pub fn run_back_to(&mut self, breakpoint: &Breakpoint<W>) -> bool {
+		self.resume_address = None;
+		self.pending_event = None;
+		while self.undo() {
+			if breakpoint.matches(self) {
+				return true;
 			}
-			modes = modes / 10;
-			i += 1;
 		}
-		(instruction, instruction_data, modes_arr)
+		false
+	}
+
+	/// Every value output so far.
+	pub fn output(&self) -> &[W] {
+		&self.output
 	}
 
-	fn get_next_instruction(&mut self) -> (Instructions, [i64; 3]) {
-		let instruction = self.memory.read(self.instruction_pointer);
-		self.instruction_pointer += 1;
-		let (instruction, instruction_data, modes) = self.parse_instruction(instruction);
-		let mut args = [0; 3];
-		let arg_count = instruction_data.arguments_count as usize;
-		for i in 0usize..arg_count {
-			match modes[i] {
+	/// Returns the output produced so far and clears it.
+	pub fn take_output(&mut self) -> Vec<W> {
+		std::mem::take(&mut self.output)
+	}
+
+	// Always inlined, so `run_unobserved` never builds the parts of
+	// `StepInfo` it doesn't use.
+	#[inline(always)]
+	fn decode_instruction(&mut self, address: usize) -> Result<StepInfo<W>, VmError<W>> {
+		let opcode = match self.decode_cache.get(address) {
+			Some(opcode) => opcode,
+			None => {
+				let opcode = decode_opcode(&self.memory.read(address))
+					.map_err(|e| VmError::from_decode_error(e, address))?;
+				self.decode_cache.insert(address, opcode);
+				opcode
+			}
+		};
+		let modes = opcode.modes();
+		let mut step = StepInfo {
+			address,
+			instruction: opcode.instruction,
+			modes: [ParameterModes::Position; MAX_ARGUMENTS],
+			operands: array::from_fn(|_| W::zero()),
+			values: array::from_fn(|_| W::zero()),
+			reads: [None; MAX_ARGUMENTS],
+			argument_count: modes.len(),
+			write_address: None,
+			written_value: None,
+			instruction_pointer: address + 1 + modes.len(),
+			event: None,
+		};
+		step.modes[..modes.len()].copy_from_slice(modes);
+		for (i, mode) in modes.iter().enumerate() {
+			let operand = self.memory.read(address + 1 + i);
+			step.values[i] = match mode {
 				ParameterModes::Position => {
-					args[i] = self
-						.memory
-						.read(self.memory.read(self.instruction_pointer) as usize)
+					let target = to_address(&operand, address)?;
+					step.reads[i] = Some(target);
+					self.memory.read(target)
 				}
-				ParameterModes::Immediate => args[i] = self.memory.read(self.instruction_pointer),
+				ParameterModes::Immediate => operand.clone(),
 				ParameterModes::Relative => {
-					args[i] = self
-						.memory
-						.read((self.base + self.memory.read(self.instruction_pointer)) as usize);
+					let target = self.overflow_policy.add(&self.base, &operand, address)?;
+					let target = to_address(&target, address)?;
+					step.reads[i] = Some(target);
+					self.memory.read(target)
 				}
 				ParameterModes::RelativeImmediate => {
-					args[i] = self.base + self.memory.read(self.instruction_pointer);
+					self.overflow_policy.add(&self.base, &operand, address)?
 				}
-			}
-			self.instruction_pointer += 1;
+			};
+			step.operands[i] = operand;
 		}
-		(instruction, args)
+		Ok(step)
 	}
 
-	fn execute_instruction(&mut self, instr: Instructions, args: [i64; 3]) {
+	// Inlined for the same reason as `decode_instruction`.
+	#[inline(always)]
+	fn execute_instruction(&mut self, step: &mut StepInfo<W>) -> Result<(), VmError<W>> {
+		let args = &step.values;
+		let address = step.address;
 		let store_adress;
-		let mut value = 0;
-		match instr {
+		let value;
+		match step.instruction {
 			Instructions::ADD => {
-				value = args[0] + args[1];
-				store_adress = args[2];
+				value = self.overflow_policy.add(&args[0], &args[1], address)?;
+				store_adress = &args[2];
 			}
 			Instructions::MUL => {
-				value = args[0] * args[1];
-				store_adress = args[2];
+				value = self.overflow_policy.mul(&args[0], &args[1], address)?;
+				store_adress = &args[2];
 			}
 			Instructions::IN => {
-				if self.next_input >= self.input.len() {
-					let handle = self.input_handle;
+				// Validate the target before consuming any input.
+				self.write_address(&args[0], address)?;
+				if self.next_input < self.input.len() {
+					value = self.input[self.next_input].clone();
+					self.next_input += 1;
+				} else if let Some(handle) = self.input_handle.as_mut() {
 					value = handle();
-				} else {
-					value = self.input[self.next_input];
+					// Keep it with the queued values so it counts as consumed input.
+					self.input.push(value.clone());
 					self.next_input += 1;
+				} else {
+					step.event = Some(ExecutionEvent::NeedsInput);
+					return Ok(());
 				}
-				store_adress = args[0];
+				store_adress = &args[0];
 			}
 			Instructions::OUT => {
-				let handle = self.output_handle;
-				handle(args[0]);
-				self.output.push(args[0]);
-				store_adress = -1;
+				if let Some(handle) = self.output_handle.as_mut() {
+					handle(args[0].clone());
+				}
+				self.output.push(args[0].clone());
+				step.event = Some(ExecutionEvent::Output(args[0].clone()));
+				return Ok(());
 			}
 			Instructions::JMP => {
-				if args[0] != 0 {
-					self.instruction_pointer = args[1] as usize;
+				if !args[0].is_zero() {
+					step.instruction_pointer = to_address(&args[1], address)?;
 				}
-				store_adress = -1;
+				return Ok(());
 			}
 			Instructions::JMPF => {
-				if args[0] == 0 {
-					self.instruction_pointer = args[1] as usize;
+				if args[0].is_zero() {
+					step.instruction_pointer = to_address(&args[1], address)?;
 				}
-				store_adress = -1;
+				return Ok(());
 			}
 			Instructions::LESS => {
-				if args[0] < args[1] {
-					value = 1;
-				} else {
-					value = 0;
-				}
-				store_adress = args[2];
+				value = W::from_i64((args[0] < args[1]) as i64);
+				store_adress = &args[2];
 			}
 			Instructions::EQ => {
-				if args[0] == args[1] {
-					value = 1;
-				} else {
-					value = 0;
-				}
-				store_adress = args[2];
+				value = W::from_i64((args[0] == args[1]) as i64);
+				store_adress = &args[2];
 			}
 			Instructions::ARB => {
-				self.base += args[0];
-				store_adress = -1;
+				self.base = self.overflow_policy.add(&self.base, &args[0], address)?;
+				return Ok(());
 			}
 			Instructions::MOV => {
-				value = args[0];
-				store_adress = args[1];
+				value = args[0].clone();
+				store_adress = &args[1];
 			}
 			Instructions::GRT => {
-				if args[0] > args[1] {
-					value = 1;
-				} else {
-					value = 0;
+				value = W::from_i64((args[0] > args[1]) as i64);
+				store_adress = &args[2];
+			}
+			Instructions::HLT => {
+				step.event = Some(ExecutionEvent::Halted);
+				return Ok(());
+			}
+		}
+		let store_adress = self.write_address(store_adress, address)?;
+		self.memory.write(store_adress, value.clone());
+		self.decode_cache.invalidate(store_adress);
+		step.write_address = Some(store_adress);
+		step.written_value = Some(value);
+		Ok(())
+	}
+
+	fn write_address(&self, value: &W, ip: usize) -> Result<usize, VmError<W>> {
+		let address = to_address(value, ip)?;
+		if address >= self.memory.max_size() {
+			return Err(VmError::AddressOutOfRange {
+				addr: value.clone(),
+				ip,
+			});
+		}
+		Ok(address)
+	}
+
+	/// Executes exactly one instruction and describes what it did.
+	///
+	/// An `IN` without available input and `HLT` don't advance the
+	/// instruction pointer; the returned `StepInfo` carries the matching
+	/// event. On error the machine is left unchanged.
+	pub fn step(&mut self) -> Result<StepInfo<W>, VmError<W>> {
+		let mut step = self.decode_instruction(self.instruction_pointer)?;
+		let mut journal = None;
+		if let Some(history) = &self.history {
+			let checkpoint = if history.needs_checkpoint() {
+				Some(self.checkpoint())
+			} else {
+				None
+			};
+			journal = Some((self.undo_record(&step), checkpoint));
+		}
+		self.execute_instruction(&mut step)?;
+		match step.event {
+			Some(ExecutionEvent::NeedsInput) => step.instruction_pointer = step.address,
+			Some(ExecutionEvent::Halted) => {
+				step.instruction_pointer = step.address;
+				// Traced once so reports see the `HLT`, but not counted: it
+				// runs again every time the halted program is resumed.
+				if !self.halted {
+					if let Some(tracer) = self.tracer.as_mut() {
+						tracer.trace(self.executed_instructions, &step, &self.base);
+					}
 				}
-				store_adress = args[2];
+				self.halted = true;
+			}
+			_ => {
+				if let (Some(history), Some((mut undo, checkpoint))) =
+					(self.history.as_mut(), journal)
+				{
+					if step.write_address.is_none() {
+						undo.write = None;
+					}
+					undo.output = matches!(step.event, Some(ExecutionEvent::Output(_)));
+					history.push(undo, checkpoint);
+				}
+				if let Some(tracer) = self.tracer.as_mut() {
+					tracer.trace(self.executed_instructions, &step, &self.base);
+				}
+				self.executed_instructions += 1;
 			}
-			Instructions::HLT => panic!("Hlt in execute_instruction"),
 		}
-		if store_adress < 0 {
-			return;
+		self.instruction_pointer = step.instruction_pointer;
+		Ok(step)
+	}
+
+	/// Captures what the decoded `step` may change before it executes.
+	fn undo_record(&self, step: &StepInfo<W>) -> UndoRecord<W> {
+		let write = step
+			.instruction
+			.data()
+			.write_argument
+			.and_then(|argument| to_address(&step.values[argument as usize], step.address).ok())
+			.map(|address| (address, self.memory.read(address)));
+		UndoRecord {
+			instruction_pointer: step.address,
+			base: match step.instruction {
+				Instructions::ARB => Some(self.base.clone()),
+				_ => None,
+			},
+			write,
+			consumed_input: step.instruction == Instructions::IN,
+			output: false,
+		}
+	}
+
+	fn budget_exhausted(&self, started: Instant) -> bool {
+		// Stepping back can take the count below the start of the budget.
+		let used = self.executed_instructions.saturating_sub(self.budget_start);
+		if let Some(limit) = self.budget.instructions {
+			if used >= limit {
+				return true;
+			}
+		}
+		if let Some(limit) = self.budget.time {
+			if used % TIME_CHECK_INTERVAL == 0 && self.budget_elapsed + started.elapsed() >= limit {
+				return true;
+			}
+		}
+		false
+	}
+
+	/// Executes instructions until the program produces output, needs input
+	/// it doesn't have, halts, or stops at a breakpoint or watchpoint. The
+	/// program can be resumed by calling this again; once halted it keeps
+	/// returning `ExecutionEvent::Halted`.
+	///
+	/// On error the instruction pointer is left on the faulting instruction.
+	pub fn run_until_event(&mut self) -> Result<ExecutionEvent<W>, VmError<W>> {
+		let started = Instant::now();
+		let result = self.run_within_budget(started);
+		self.budget_elapsed += started.elapsed();
+		result
+	}
+
+	/// Whether no breakpoint, watchpoint, tracer or history needs to see
+	/// each step, so `run_unobserved` can run the program.
+	fn is_unobserved(&self) -> bool {
+		self.breakpoints.is_empty()
+			&& self.watchpoints.is_empty()
+			&& self.tracer.is_none()
+			&& self.history.is_none()
+	}
+
+	/// `run_within_budget` without the per-step checks, for programs that
+	/// nothing observes.
+	fn run_unobserved(&mut self, started: Instant) -> Result<ExecutionEvent<W>, VmError<W>> {
+		loop {
+			if self.budget_exhausted(started) {
+				return Ok(ExecutionEvent::BudgetExhausted);
+			}
+			let mut step = self.decode_instruction(self.instruction_pointer)?;
+			self.execute_instruction(&mut step)?;
+			match step.event {
+				Some(ExecutionEvent::NeedsInput) => return Ok(ExecutionEvent::NeedsInput),
+				Some(ExecutionEvent::Halted) => {
+					self.halted = true;
+					return Ok(ExecutionEvent::Halted);
+				}
+				event => {
+					self.instruction_pointer = step.instruction_pointer;
+					self.executed_instructions += 1;
+					if let Some(event) = event {
+						return Ok(event);
+					}
+				}
+			}
+		}
+	}
+
+	fn run_within_budget(&mut self, started: Instant) -> Result<ExecutionEvent<W>, VmError<W>> {
+		if let Some(event) = self.pending_event.take() {
+			return Ok(event);
+		}
+		if self.is_unobserved() {
+			return self.run_unobserved(started);
+		}
+		loop {
+			if self.budget_exhausted(started) {
+				return Ok(ExecutionEvent::BudgetExhausted);
+			}
+			if self.resume_address != Some(self.instruction_pointer) {
+				if let Some(id) = self.hit_breakpoint() {
+					let ip = self.instruction_pointer;
+					self.resume_address = Some(ip);
+					return Ok(ExecutionEvent::Breakpoint { id, ip });
+				}
+			}
+			let step = self.step()?;
+			// An `IN` still waiting for input hasn't run yet, so its breakpoint
+			// stays skipped until it does.
+			if step.event != Some(ExecutionEvent::NeedsInput) {
+				self.resume_address = None;
+			}
+			let watch = if self.watchpoints.is_empty() {
+				None
+			} else {
+				self.hit_watchpoint(&step)
+			};
+			match (step.event, watch) {
+				(Some(event), watch) => {
+					self.pending_event = watch;
+					return Ok(event);
+				}
+				(None, Some(watch)) => return Ok(watch),
+				(None, None) => {}
+			}
 		}
-		self.memory.write(store_adress as usize, value);
 	}
 
-	pub fn run(mut self) -> Vec<i64> {
-		let (mut instruction, mut args) = self.get_next_instruction();
-		while instruction != Instructions::HLT {
-			self.execute_instruction(instruction, args);
-			let res = self.get_next_instruction();
-			instruction = res.0;
-			args = res.1;
+	/// Runs the program until it halts and returns all of its output.
+	///
+	/// The program is kept on errors: after `VmError::BudgetExhausted` it can
+	/// be given more budget with `set_budget` and run again, and after
+	/// `VmError::MissingInput` it resumes once input is pushed.
+	pub fn run(&mut self) -> Result<Vec<W>, VmError<W>> {
+		loop {
+			match self.run_until_event()? {
+				ExecutionEvent::Output(_)
+				| ExecutionEvent::Breakpoint { .. }
+				| ExecutionEvent::Watchpoint { .. } => {}
+				ExecutionEvent::Halted => return Ok(self.output.clone()),
+				ExecutionEvent::NeedsInput => {
+					return Err(VmError::MissingInput {
+						ip: self.instruction_pointer,
+					})
+				}
+				ExecutionEvent::BudgetExhausted => {
+					return Err(VmError::BudgetExhausted {
+						ip: self.instruction_pointer,
+					})
+				}
+			}
 		}
-		self.output
+	}
+}
+
+fn to_address<W: Word>(value: &W, ip: usize) -> Result<usize, VmError<W>> {
+	match value.to_i64() {
+		Some(addr) if addr < 0 => Err(VmError::NegativeAddress {
+			addr: value.clone(),
+			ip,
+		}),
+		Some(addr) => usize::try_from(addr).map_err(|_| VmError::AddressOutOfRange {
+			addr: value.clone(),
+			ip,
+		}),
+		None => Err(VmError::AddressOutOfRange {
+			addr: value.clone(),
+			ip,
+		}),
 	}
 }
@@ -1,39 +1,160 @@
+pub mod ascii;
+pub mod coverage;
 pub mod instructions;
 pub mod interpreter;
+pub mod network;
+pub mod pipeline;
+pub mod profile;
+pub mod source_map;
+pub mod state;
+#[cfg(feature = "async")]
+pub mod stream;
+pub mod threaded;
+pub mod trace;
+pub mod word;
 
-use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Arc;
+use word::Word;
 
+/// Number of cells in a memory page.
+pub const PAGE_SIZE: usize = 1024;
+/// Maximum size of a `Memory` created with `Memory::new` or
+/// `Memory::from_words`, in cells.
+pub const DEFAULT_MAX_SIZE: usize = 1 << 30;
+
+/// Program memory, split in fixed-size pages that are allocated on the
+/// first write to them. Cells that were never written read as zero.
+///
+/// Pages are copy-on-write: cloning a memory takes constant time and clones
+/// share every page until one of them writes to it.
 #[derive(Clone)]
-pub struct Memory {
-    initial_data: Vec<i64>,
-    expanded_memory: HashMap<usize, i64>,
+pub struct Memory<W = i64> {
+    pages: Arc<Vec<Option<Arc<[W]>>>>,
+    len: usize,
+    max_size: usize,
 }
 
-impl<'a> Memory {
+impl Memory {
+    /// Creates an `i64` memory holding `p_data`. Use `from_words` for other
+    /// word types.
     pub fn new(p_data: Vec<i64>) -> Memory {
+        Memory::from_words(p_data)
+    }
+}
+
+impl<W: Word> Memory<W> {
+    /// Creates a memory of any word type holding `p_data`.
+    pub fn from_words(p_data: Vec<W>) -> Memory<W> {
+        Memory::with_max_size(p_data, DEFAULT_MAX_SIZE)
+    }
+
+    /// Creates a memory whose addresses must stay below `max_size`.
+    ///
+    /// # Panics
+    /// Panics if `p_data` doesn't fit in `max_size` cells.
+    pub fn with_max_size(p_data: Vec<W>, max_size: usize) -> Memory<W> {
+        assert!(
+            p_data.len() <= max_size,
+            "Program of {} cells doesn't fit in a memory of {} cells",
+            p_data.len(),
+            max_size
+        );
+        let len = p_data.len();
+        let mut data = p_data.into_iter();
+        let mut pages = Vec::with_capacity(len.div_ceil(PAGE_SIZE));
+        while pages.len() * PAGE_SIZE < len {
+            let mut page: Vec<W> = data.by_ref().take(PAGE_SIZE).collect();
+            page.resize(PAGE_SIZE, W::zero());
+            pages.push(Some(page.into()));
+        }
+        Memory {
+            pages: Arc::new(pages),
+            len,
+            max_size,
+        }
+    }
+
+    /// Creates a memory of `len` zero cells whose addresses must stay below
+    /// `max_size`. No page is allocated until it's written.
+    ///
+    /// # Panics
+    /// Panics if `len` is above `max_size`.
+    pub fn zeroed(len: usize, max_size: usize) -> Memory<W> {
+        assert!(
+            len <= max_size,
+            "Memory of {} cells doesn't fit in a memory of {} cells",
+            len,
+            max_size
+        );
         Memory {
-            initial_data: p_data,
-            expanded_memory: HashMap::new(),
+            pages: Arc::new(Vec::new()),
+            len,
+            max_size,
         }
     }
 
-    pub fn read(&self, index: usize) -> i64 {
-        if index >= self.initial_data.len() {
-            if !self.expanded_memory.contains_key(&index) {
-                0
-            } else {
-                self.expanded_memory[&index]
-            }
-        } else {
-            self.initial_data[index]
+    pub fn read(&self, index: usize) -> W {
+        match self.pages.get(index / PAGE_SIZE) {
+            Some(Some(page)) => page[index % PAGE_SIZE].clone(),
+            _ => W::zero(),
         }
     }
 
-    pub fn write(&mut self, index: usize, value: i64) {
-        if index >= self.initial_data.len() {
-            self.expanded_memory.insert(index, value);
-        } else {
-            self.initial_data[index] = value;
+    /// # Panics
+    /// Panics if `index` is not below `max_size`.
+    pub fn write(&mut self, index: usize, value: W) {
+        assert!(
+            index < self.max_size,
+            "Address {} is outside of a memory of {} cells",
+            index,
+            self.max_size
+        );
+        let page_index = index / PAGE_SIZE;
+        self.len = self.len.max(index + 1);
+        let allocated = matches!(self.pages.get(page_index), Some(Some(_)));
+        if !allocated && value.is_zero() {
+            // Untouched pages already read as zero.
+            return;
         }
+        let pages = Arc::make_mut(&mut self.pages);
+        if page_index >= pages.len() {
+            pages.resize_with(page_index + 1, || None);
+        }
+        let page = pages[page_index].get_or_insert_with(|| vec![W::zero(); PAGE_SIZE].into());
+        Arc::make_mut(page)[index % PAGE_SIZE] = value;
+    }
+
+    /// Number of cells addresses must stay below.
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Returns one past the highest address that was loaded or written.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Reads every address in `range`, including untouched (zero) cells.
+    pub fn read_range(&self, range: Range<usize>) -> Vec<W> {
+        range.map(|index| self.read(index)).collect()
+    }
+
+    /// Iterates over every non zero cell as `(address, value)` in address order.
+    pub fn iter_nonzero(&self) -> impl Iterator<Item = (usize, W)> + '_ {
+        self.pages
+            .iter()
+            .enumerate()
+            .filter_map(|(page_index, page)| page.as_ref().map(|page| (page_index, page)))
+            .flat_map(|(page_index, page)| {
+                page.iter()
+                    .enumerate()
+                    .filter(|(_, value)| !value.is_zero())
+                    .map(move |(offset, value)| (page_index * PAGE_SIZE + offset, value.clone()))
+            })
     }
 }
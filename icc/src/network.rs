@@ -0,0 +1,324 @@
+use crate::interpreter::{Budget, ExecutionEvent, IntCodeProgram, VmError};
+use crate::word::Word;
+use crate::Memory;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
+
+/// Address of the monitor: packets sent to it are passed to the network's
+/// `Monitor` instead of a machine.
+pub const NAT_ADDRESS: usize = 255;
+
+/// A packet sent with three `OUT` instructions: destination, `x` and `y`.
+/// The receiving machine reads `x` and then `y`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Packet<W = i64> {
+	pub destination: usize,
+	pub x: W,
+	pub y: W,
+}
+
+/// What the monitor wants the network to do next.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum MonitorAction<W = i64> {
+	Continue,
+	/// Delivers a packet to the machine it's addressed to.
+	Send(Packet<W>),
+	/// Stops `Network::run` with `StopReason::Monitor`.
+	Stop,
+}
+
+/// Hooks called for packets sent to `NAT_ADDRESS` and when the network goes
+/// idle. Both default to doing nothing.
+pub trait Monitor<W> {
+	fn packet(&mut self, _packet: Packet<W>) -> MonitorAction<W> {
+		MonitorAction::Continue
+	}
+
+	/// Called when every queue is empty and every running machine is polling
+	/// for input. Unless the monitor sends a packet or stops the network,
+	/// `run` returns `StopReason::Idle`.
+	fn idle(&mut self) -> MonitorAction<W> {
+		MonitorAction::Continue
+	}
+}
+
+/// Monitor that ignores everything.
+impl<W> Monitor<W> for () {}
+
+/// How machines take turns. Scheduling is deterministic: the same program
+/// and policy always deliver the same packets in the same order.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Scheduling {
+	/// Machines run one after another, in address order. Each turn lasts
+	/// until the machine polls an empty queue twice in a row, halts, or has
+	/// executed `quantum` instructions. Polls in a row may span turns; any
+	/// output or packet received in between breaks the streak.
+	RoundRobin { quantum: u64 },
+}
+
+impl Default for Scheduling {
+	fn default() -> Scheduling {
+		Scheduling::RoundRobin { quantum: 10_000 }
+	}
+}
+
+/// Why `Network::run` returned.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StopReason {
+	/// The monitor returned `MonitorAction::Stop`.
+	Monitor,
+	/// The network went idle and the monitor didn't wake it up.
+	Idle,
+	/// Every machine halted.
+	Halted,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum NetworkError<W = i64> {
+	/// A machine failed.
+	Vm { machine: usize, error: VmError<W> },
+	/// A packet was sent to an address with no machine behind it. `machine`
+	/// is `NAT_ADDRESS` for packets sent by the monitor.
+	UnknownDestination { machine: usize, destination: W },
+}
+
+impl<W: Word> fmt::Display for NetworkError<W> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			NetworkError::Vm { machine, error } => write!(f, "Machine {}: {}", machine, error),
+			NetworkError::UnknownDestination {
+				machine,
+				destination,
+			} => write!(
+				f,
+				"Machine {} sent a packet to unknown address {}",
+				machine, destination
+			),
+		}
+	}
+}
+
+impl<W: Word> error::Error for NetworkError<W> {}
+
+struct Node<W> {
+	program: IntCodeProgram<W>,
+	queue: VecDeque<(W, W)>,
+	/// Values of a packet being sent.
+	sending: Vec<W>,
+	/// The machine polled an empty queue and hasn't output anything or
+	/// received a packet since.
+	polled_empty: bool,
+	/// The machine polled an empty queue a second time in a row.
+	idle: bool,
+	halted: bool,
+}
+
+/// Copies of one program that exchange packets. Machine `i` reads `i` as
+/// its first input, then packets addressed to it; `IN` reads `-1` when its
+/// queue is empty.
+pub struct Network<W = i64> {
+	nodes: Vec<Node<W>>,
+	monitor: Box<dyn Monitor<W>>,
+	scheduling: Scheduling,
+}
+
+impl<W: Word> Network<W> {
+	/// Boots `machines` copies of `memory`.
+	pub fn new<M>(memory: &Memory<W>, machines: usize, monitor: M) -> Network<W>
+	where
+		M: Monitor<W> + 'static,
+	{
+		let nodes = (0..machines)
+			.map(|address| Node {
+				program: IntCodeProgram::from_memory(
+					memory.clone(),
+					vec![W::from_i64(address as i64)],
+				),
+				queue: VecDeque::new(),
+				sending: Vec::new(),
+				polled_empty: false,
+				idle: false,
+				halted: false,
+			})
+			.collect();
+		Network {
+			nodes,
+			monitor: Box::new(monitor),
+			scheduling: Scheduling::default(),
+		}
+	}
+
+	pub fn set_scheduling(&mut self, scheduling: Scheduling) {
+		self.scheduling = scheduling;
+	}
+
+	pub fn scheduling(&self) -> Scheduling {
+		self.scheduling
+	}
+
+	pub fn len(&self) -> usize {
+		self.nodes.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.nodes.is_empty()
+	}
+
+	pub fn machine(&self, address: usize) -> &IntCodeProgram<W> {
+		&self.nodes[address].program
+	}
+
+	pub fn machine_mut(&mut self, address: usize) -> &mut IntCodeProgram<W> {
+		&mut self.nodes[address].program
+	}
+
+	/// Number of packets waiting to be read by a machine.
+	pub fn queue_len(&self, address: usize) -> usize {
+		self.nodes[address].queue.len()
+	}
+
+	/// Queues a packet for its destination machine.
+	pub fn send(&mut self, packet: Packet<W>) -> Result<(), NetworkError<W>> {
+		match self.nodes.get_mut(packet.destination) {
+			Some(node) => {
+				node.queue.push_back((packet.x, packet.y));
+				node.idle = false;
+				Ok(())
+			}
+			None => Err(NetworkError::UnknownDestination {
+				machine: NAT_ADDRESS,
+				destination: W::from_i64(packet.destination as i64),
+			}),
+		}
+	}
+
+	fn is_idle(&self) -> bool {
+		self.nodes
+			.iter()
+			.all(|node| node.halted || (node.idle && node.queue.is_empty()))
+	}
+
+	fn apply(&mut self, action: MonitorAction<W>) -> Result<Option<StopReason>, NetworkError<W>> {
+		match action {
+			MonitorAction::Continue => Ok(None),
+			MonitorAction::Send(packet) => self.send(packet).map(|_| None),
+			MonitorAction::Stop => Ok(Some(StopReason::Monitor)),
+		}
+	}
+
+	/// Routes a packet sent by `machine`.
+	fn route(
+		&mut self,
+		machine: usize,
+		destination: W,
+		x: W,
+		y: W,
+	) -> Result<Option<StopReason>, NetworkError<W>> {
+		let address = destination.to_i64().and_then(|d| usize::try_from(d).ok());
+		match address {
+			Some(NAT_ADDRESS) => {
+				let action = self.monitor.packet(Packet {
+					destination: NAT_ADDRESS,
+					x,
+					y,
+				});
+				self.apply(action)
+			}
+			Some(address) if address < self.nodes.len() => {
+				self.nodes[address].queue.push_back((x, y));
+				self.nodes[address].idle = false;
+				Ok(None)
+			}
+			_ => Err(NetworkError::UnknownDestination {
+				machine,
+				destination,
+			}),
+		}
+	}
+
+	/// Runs one turn of `machine`.
+	fn turn(&mut self, machine: usize) -> Result<Option<StopReason>, NetworkError<W>> {
+		let Scheduling::RoundRobin { quantum } = self.scheduling;
+		let node = &mut self.nodes[machine];
+		if node.halted {
+			return Ok(None);
+		}
+		node.program.set_budget(Budget::instructions(quantum));
+		// Only a turn that ends on a second empty poll leaves the machine
+		// idle; one cut short by the quantum may still be busy.
+		let mut idle = false;
+		loop {
+			let node = &mut self.nodes[machine];
+			let event = node
+				.program
+				.run_until_event()
+				.map_err(|error| NetworkError::Vm { machine, error })?;
+			match event {
+				ExecutionEvent::Output(value) => {
+					// Packets are built from `sending`, so the program's own
+					// copy of its output would only grow.
+					node.program.take_output();
+					node.polled_empty = false;
+					node.sending.push(value);
+					if node.sending.len() == 3 {
+						let y = node.sending.pop().unwrap();
+						let x = node.sending.pop().unwrap();
+						let destination = node.sending.pop().unwrap();
+						if let Some(stop) = self.route(machine, destination, x, y)? {
+							return Ok(Some(stop));
+						}
+					}
+				}
+				ExecutionEvent::NeedsInput => match node.queue.pop_front() {
+					Some((x, y)) => {
+						node.polled_empty = false;
+						node.program.push_input(x);
+						node.program.push_input(y);
+					}
+					None if node.polled_empty => {
+						idle = true;
+						break;
+					}
+					None => {
+						node.polled_empty = true;
+						node.program.push_input(W::from_i64(-1));
+					}
+				},
+				ExecutionEvent::Halted => {
+					node.halted = true;
+					break;
+				}
+				_ => break,
+			}
+		}
+		let node = &mut self.nodes[machine];
+		node.idle = idle && node.queue.is_empty();
+		Ok(None)
+	}
+
+	/// Runs the machines until the monitor stops the network, it goes idle
+	/// without the monitor waking it up, or every machine halts.
+	pub fn run(&mut self) -> Result<StopReason, NetworkError<W>> {
+		loop {
+			for machine in 0..self.nodes.len() {
+				if let Some(stop) = self.turn(machine)? {
+					return Ok(stop);
+				}
+			}
+			if self.nodes.iter().all(|node| node.halted) {
+				return Ok(StopReason::Halted);
+			}
+			if self.is_idle() {
+				let action = self.monitor.idle();
+				if let Some(stop) = self.apply(action)? {
+					return Ok(stop);
+				}
+				if self.is_idle() {
+					return Ok(StopReason::Idle);
+				}
+			}
+		}
+	}
+}
@@ -0,0 +1,185 @@
+use crate::interpreter::{ExecutionEvent, IntCodeProgram, VmError};
+use crate::word::Word;
+use crate::Memory;
+use std::error;
+use std::fmt;
+
+/// Where the last stage's output goes.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Topology {
+	/// The last stage's output is only the result.
+	Chain,
+	/// The last stage's output is also fed back to the first stage.
+	Ring,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum PipelineError<W = i64> {
+	/// A stage failed.
+	Vm { stage: usize, error: VmError<W> },
+	/// Every running stage is waiting for input that will never come.
+	Deadlock,
+	/// A stage ran out of the `Budget` set on it.
+	BudgetExhausted { stage: usize },
+	/// The last stage halted without writing any output.
+	NoSignal,
+}
+
+impl<W: Word> fmt::Display for PipelineError<W> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			PipelineError::Vm { stage, error } => write!(f, "Stage {}: {}", stage, error),
+			PipelineError::Deadlock => write!(f, "Every stage is waiting for input"),
+			PipelineError::BudgetExhausted { stage } => {
+				write!(f, "Stage {} ran out of its execution budget", stage)
+			}
+			PipelineError::NoSignal => write!(f, "The last stage halted without output"),
+		}
+	}
+}
+
+impl<W: Word> error::Error for PipelineError<W> {}
+
+/// Programs wired output to input: every value a stage outputs is the next
+/// stage's input.
+pub struct Pipeline<W = i64> {
+	stages: Vec<IntCodeProgram<W>>,
+	topology: Topology,
+}
+
+impl<W: Word> Pipeline<W> {
+	pub fn new(stages: Vec<IntCodeProgram<W>>, topology: Topology) -> Pipeline<W> {
+		Pipeline { stages, topology }
+	}
+
+	/// One copy of `memory` per phase setting, each reading its phase as
+	/// its first input.
+	pub fn with_phases(memory: &Memory<W>, phases: &[W], topology: Topology) -> Pipeline<W> {
+		let stages = phases
+			.iter()
+			.map(|phase| IntCodeProgram::from_memory(memory.clone(), vec![phase.clone()]))
+			.collect();
+		Pipeline::new(stages, topology)
+	}
+
+	pub fn topology(&self) -> Topology {
+		self.topology
+	}
+
+	pub fn len(&self) -> usize {
+		self.stages.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.stages.is_empty()
+	}
+
+	pub fn stage(&self, index: usize) -> &IntCodeProgram<W> {
+		&self.stages[index]
+	}
+
+	pub fn stage_mut(&mut self, index: usize) -> &mut IntCodeProgram<W> {
+		&mut self.stages[index]
+	}
+
+	/// Feeds `signal` to the first stage and runs the stages in turn, each
+	/// until it waits for input, until the last one halts. Returns the last
+	/// value it output. An empty pipeline returns `signal`. Stages don't stop
+	/// at their breakpoints or watchpoints, as with `IntCodeProgram::run`.
+	pub fn run(&mut self, signal: W) -> Result<W, PipelineError<W>> {
+		let last = match self.stages.len() {
+			0 => return Ok(signal),
+			len => len - 1,
+		};
+		self.stages[0].push_input(signal);
+		let mut halted = vec![false; self.stages.len()];
+		let mut result = None;
+		loop {
+			let executed: u64 = self.executed();
+			for (stage, halted) in halted.iter_mut().enumerate() {
+				if *halted {
+					continue;
+				}
+				loop {
+					let event = self.stages[stage]
+						.run_until_event()
+						.map_err(|error| PipelineError::Vm { stage, error })?;
+					match event {
+						ExecutionEvent::Output(value) if stage == last => {
+							if self.topology == Topology::Ring {
+								self.stages[0].push_input(value.clone());
+							}
+							result = Some(value);
+						}
+						ExecutionEvent::Output(value) => self.stages[stage + 1].push_input(value),
+						ExecutionEvent::Halted if stage == last => {
+							return result.ok_or(PipelineError::NoSignal);
+						}
+						ExecutionEvent::Halted => {
+							*halted = true;
+							break;
+						}
+						ExecutionEvent::BudgetExhausted => {
+							return Err(PipelineError::BudgetExhausted { stage });
+						}
+						ExecutionEvent::Breakpoint { .. } | ExecutionEvent::Watchpoint { .. } => {}
+						ExecutionEvent::NeedsInput => break,
+					}
+				}
+			}
+			if self.executed() == executed {
+				return Err(PipelineError::Deadlock);
+			}
+		}
+	}
+
+	fn executed(&self) -> u64 {
+		self.stages
+			.iter()
+			.map(|stage| stage.instructions_executed())
+			.sum()
+	}
+}
+
+/// Calls `f` with every ordering of `items[start..]`.
+fn permute<W, E>(
+	items: &mut [W],
+	start: usize,
+	f: &mut dyn FnMut(&[W]) -> Result<(), E>,
+) -> Result<(), E> {
+	if start == items.len() {
+		return f(items);
+	}
+	for i in start..items.len() {
+		items.swap(start, i);
+		permute(items, start + 1, f)?;
+		items.swap(start, i);
+	}
+	Ok(())
+}
+
+/// Runs a pipeline of `memory` for every ordering of `phases` and returns the
+/// ordering giving the highest signal, with that signal. The first ordering
+/// found wins ties.
+pub fn best_phases<W: Word>(
+	memory: &Memory<W>,
+	phases: &[W],
+	topology: Topology,
+	signal: W,
+) -> Result<(Vec<W>, W), PipelineError<W>> {
+	let mut best: Option<(Vec<W>, W)> = None;
+	let mut ordering = phases.to_vec();
+	permute(&mut ordering, 0, &mut |phases| {
+		let result = Pipeline::with_phases(memory, phases, topology).run(signal.clone())?;
+		let better = match &best {
+			Some((_, best_result)) => result > *best_result,
+			None => true,
+		};
+		if better {
+			best = Some((phases.to_vec(), result));
+		}
+		Ok(())
+	})?;
+	// There is always at least one ordering, even of no phases.
+	Ok(best.unwrap())
+}
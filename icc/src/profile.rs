@@ -0,0 +1,174 @@
+use crate::instructions::Instructions;
+use crate::interpreter::StepInfo;
+use crate::source_map::SourceMap;
+use crate::trace::Tracer;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// Counts how often every address is executed, read and written. Set it
+/// with `IntCodeProgram::set_tracer`, wrapped in an `Arc<Mutex<_>>` to read
+/// the counts back.
+#[derive(Clone, Default, Debug)]
+pub struct Profiler {
+	cycles: u64,
+	/// Instructions traced, which also counts the final `HLT`.
+	steps: u64,
+	/// Executions of each address, with the instruction last found there.
+	executions: HashMap<usize, (u64, Instructions)>,
+	instructions: HashMap<Instructions, u64>,
+	reads: HashMap<usize, u64>,
+	writes: HashMap<usize, u64>,
+}
+
+impl<W> Tracer<W> for Profiler {
+	fn trace(&mut self, _step_number: u64, step: &StepInfo<W>, _relative_base: &W) {
+		// `HLT` doesn't count as executed, like in
+		// `IntCodeProgram::instructions_executed`.
+		if step.instruction != Instructions::HLT {
+			self.cycles += 1;
+		}
+		self.steps += 1;
+		let execution = self
+			.executions
+			.entry(step.address)
+			.or_insert((0, step.instruction));
+		execution.0 += 1;
+		execution.1 = step.instruction;
+		*self.instructions.entry(step.instruction).or_insert(0) += 1;
+		for address in step.read_addresses() {
+			*self.reads.entry(address).or_insert(0) += 1;
+		}
+		if let Some(address) = step.write_address {
+			*self.writes.entry(address).or_insert(0) += 1;
+		}
+	}
+}
+
+/// Sorts `(key, count)` pairs by descending count, then by key.
+fn by_count<K: Ord + Copy>(counts: impl Iterator<Item = (K, u64)>) -> Vec<(K, u64)> {
+	let mut counts: Vec<(K, u64)> = counts.collect();
+	counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+	counts
+}
+
+impl Profiler {
+	pub fn new() -> Profiler {
+		Profiler::default()
+	}
+
+	/// Total number of instructions executed, not counting the final `HLT`.
+	pub fn cycles(&self) -> u64 {
+		self.cycles
+	}
+
+	pub fn executions(&self, address: usize) -> u64 {
+		self.executions.get(&address).map_or(0, |(count, _)| *count)
+	}
+
+	pub fn instruction_count(&self, instruction: Instructions) -> u64 {
+		self.instructions.get(&instruction).copied().unwrap_or(0)
+	}
+
+	/// Reads of `address` by position and relative mode parameters.
+	pub fn reads(&self, address: usize) -> u64 {
+		self.reads.get(&address).copied().unwrap_or(0)
+	}
+
+	pub fn writes(&self, address: usize) -> u64 {
+		self.writes.get(&address).copied().unwrap_or(0)
+	}
+
+	/// The `count` most executed addresses with how often they ran, most
+	/// executed first.
+	pub fn hot_addresses(&self, count: usize) -> Vec<(usize, u64)> {
+		let mut hot = by_count(
+			self.executions
+				.iter()
+				.map(|(address, (count, _))| (*address, *count)),
+		);
+		hot.truncate(count);
+		hot
+	}
+
+	fn percent(&self, count: u64) -> f64 {
+		match self.steps {
+			0 => 0.0,
+			steps => count as f64 * 100.0 / steps as f64,
+		}
+	}
+
+	/// Formats a report of the `top` hottest addresses, the instruction mix
+	/// and the most accessed memory cells. With a source map, addresses are
+	/// annotated with their label and source line.
+	pub fn report(&self, top: usize, source_map: Option<&SourceMap>) -> String {
+		let mut report = String::new();
+		let annotate = |address: usize| -> String {
+			let map = match source_map {
+				Some(map) => map,
+				None => return String::new(),
+			};
+			let label = map
+				.label_at(address)
+				.map(|label| format!("<{}> ", label))
+				.unwrap_or_default();
+			match (map.line(address), map.source_at(address)) {
+				(Some(line), Some(source)) => format!("{}{}: {}", label, line, source.trim()),
+				(Some(line), None) => format!("{}line {}", label, line),
+				_ => label,
+			}
+		};
+
+		writeln!(report, "Executed {} instructions", self.cycles).unwrap();
+		writeln!(report, "\nHot addresses:").unwrap();
+		writeln!(
+			report,
+			"{:>12} {:>7} {:>8}  {:<5} source",
+			"count", "%", "address", "instr"
+		)
+		.unwrap();
+		for (address, count) in self.hot_addresses(top) {
+			writeln!(
+				report,
+				"{:>12} {:>6.2}% {:>8}  {:<5} {}",
+				count,
+				self.percent(count),
+				address,
+				self.executions[&address].1.to_string(),
+				annotate(address)
+			)
+			.unwrap();
+		}
+
+		writeln!(report, "\nInstruction mix:").unwrap();
+		let instructions = by_count(
+			self.instructions
+				.iter()
+				.map(|(instruction, count)| (instruction.get_instruction_opc(), *count)),
+		);
+		for (opc, count) in instructions {
+			let instruction = Instructions::get_instruction_from_opc(opc).unwrap();
+			writeln!(
+				report,
+				"  {:<5} {:>12} {:>6.2}%",
+				instruction.to_string(),
+				count,
+				self.percent(count)
+			)
+			.unwrap();
+		}
+
+		for (title, accesses) in [("reads", &self.reads), ("writes", &self.writes)] {
+			writeln!(report, "\nMost {}:", title).unwrap();
+			let mut accesses = by_count(accesses.iter().map(|(address, count)| (*address, *count)));
+			accesses.truncate(top);
+			for (address, count) in accesses {
+				let label = source_map
+					.and_then(|map| map.label_at(address))
+					.map(|label| format!(" <{}>", label))
+					.unwrap_or_default();
+				writeln!(report, "{:>12} {:>8}{}", count, address, label).unwrap();
+			}
+		}
+		report
+	}
+}
@@ -0,0 +1,105 @@
+use crate::instructions::Instructions;
+use std::collections::BTreeMap;
+
+/// Maps the addresses of an assembled program back to the assembly source it
+/// was built from. Line numbers start at 1.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct SourceMap {
+	file: String,
+	lines: Vec<String>,
+	/// Address of every instruction word, with the instruction, its size
+	/// and source line.
+	instructions: BTreeMap<usize, (Instructions, usize, usize)>,
+	labels: BTreeMap<String, usize>,
+}
+
+impl SourceMap {
+	pub fn new(file: String, lines: Vec<String>) -> SourceMap {
+		SourceMap {
+			file,
+			lines,
+			instructions: BTreeMap::new(),
+			labels: BTreeMap::new(),
+		}
+	}
+
+	/// Name of the source file.
+	pub fn file(&self) -> &str {
+		&self.file
+	}
+
+	/// Records an instruction of `size` cells starting at `address`.
+	pub fn add_instruction(
+		&mut self,
+		address: usize,
+		instruction: Instructions,
+		size: usize,
+		line: usize,
+	) {
+		self.instructions.insert(address, (instruction, size, line));
+	}
+
+	pub fn add_label(&mut self, name: String, address: usize) {
+		self.labels.insert(name, address);
+	}
+
+	/// Address of the instruction that contains `address`, if any.
+	pub fn instruction_start(&self, address: usize) -> Option<usize> {
+		match self.instructions.range(..=address).next_back() {
+			Some((start, (_, size, _))) if address < start + size => Some(*start),
+			_ => None,
+		}
+	}
+
+	/// Source line of the instruction that contains `address`.
+	pub fn line(&self, address: usize) -> Option<usize> {
+		let start = self.instruction_start(address)?;
+		Some(self.instructions[&start].2)
+	}
+
+	/// Instruction assembled at exactly `address`.
+	pub fn instruction(&self, address: usize) -> Option<Instructions> {
+		self.instructions
+			.get(&address)
+			.map(|(instruction, _, _)| *instruction)
+	}
+
+	/// Text of a source line, if the map was built with the source.
+	pub fn source_line(&self, line: usize) -> Option<&str> {
+		match line {
+			0 => None,
+			_ => self.lines.get(line - 1).map(String::as_str),
+		}
+	}
+
+	/// Source text of the instruction that contains `address`.
+	pub fn source_at(&self, address: usize) -> Option<&str> {
+		self.source_line(self.line(address)?)
+	}
+
+	/// Every instruction as `(address, line)`, in address order.
+	pub fn instructions(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+		self.instructions
+			.iter()
+			.map(|(address, (_, _, line))| (*address, *line))
+	}
+
+	pub fn label_address(&self, name: &str) -> Option<usize> {
+		self.labels.get(name).copied()
+	}
+
+	/// Label defined at exactly `address`.
+	pub fn label_at(&self, address: usize) -> Option<&str> {
+		self.labels
+			.iter()
+			.find(|(_, label_address)| **label_address == address)
+			.map(|(name, _)| name.as_str())
+	}
+
+	/// Every label as `(name, address)`, sorted by name.
+	pub fn labels(&self) -> impl Iterator<Item = (&str, usize)> {
+		self.labels
+			.iter()
+			.map(|(name, address)| (name.as_str(), *address))
+	}
+}
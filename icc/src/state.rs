@@ -0,0 +1,249 @@
+use crate::interpreter::OverflowPolicy;
+use crate::word::Word;
+use crate::{Memory, DEFAULT_MAX_SIZE};
+use std::error;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+/// Version written in the header of state files. Files with any other
+/// version are rejected.
+pub const STATE_VERSION: u32 = 1;
+
+/// Largest memory length a state file may declare, in cells.
+pub const MAX_STATE_MEMORY: usize = DEFAULT_MAX_SIZE;
+
+const HEADER: &str = "icc-state";
+
+/// Snapshot of everything a program needs to resume where it left off,
+/// taken with `IntCodeProgram::save_state`. Input and output handles,
+/// breakpoints, the budget, the tracer and the history aren't part of it.
+///
+/// States are written as text:
+///
+/// ```text
+/// icc-state 1
+/// ip 41
+/// base 65
+/// executed 130
+/// overflow checked
+/// input 1 4
+/// output 0,1,1
+/// memory 70 1073741824
+/// cells 0 109,65,203
+/// cells 65 4,1,2
+/// ```
+///
+/// `input` starts with how many of the values were consumed, `memory` gives
+/// the memory's length and maximum size, and each `cells` line holds a run of
+/// cells starting at an address. Cells not listed are zero.
+#[derive(Clone)]
+pub struct MachineState<W = i64> {
+	pub memory: Memory<W>,
+	pub instruction_pointer: usize,
+	pub relative_base: W,
+	/// Every input value, consumed or not.
+	pub input: Vec<W>,
+	/// Number of values at the start of `input` already consumed.
+	pub consumed_input: usize,
+	pub output: Vec<W>,
+	pub instructions_executed: u64,
+	pub overflow_policy: OverflowPolicy,
+}
+
+#[derive(Debug)]
+pub enum StateError {
+	Io(io::Error),
+	/// The file doesn't start with a state header.
+	NotAState,
+	UnsupportedVersion(String),
+	/// A line couldn't be parsed. Lines are numbered from 1.
+	Invalid {
+		line: usize,
+		message: String,
+	},
+}
+
+impl fmt::Display for StateError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			StateError::Io(e) => write!(f, "{}", e),
+			StateError::NotAState => write!(f, "Not an icc state file"),
+			StateError::UnsupportedVersion(version) => {
+				write!(f, "Unsupported state version {}", version)
+			}
+			StateError::Invalid { line, message } => write!(f, "Line {}: {}", line, message),
+		}
+	}
+}
+
+impl error::Error for StateError {}
+
+impl From<io::Error> for StateError {
+	fn from(e: io::Error) -> StateError {
+		StateError::Io(e)
+	}
+}
+
+fn overflow_policy_name(policy: OverflowPolicy) -> &'static str {
+	match policy {
+		OverflowPolicy::Checked => "checked",
+		OverflowPolicy::Wrapping => "wrapping",
+		OverflowPolicy::Saturating => "saturating",
+	}
+}
+
+fn write_list<W: fmt::Display>(writer: &mut impl Write, values: &[W]) -> io::Result<()> {
+	for (i, value) in values.iter().enumerate() {
+		let separator = if i == 0 { "" } else { "," };
+		write!(writer, "{}{}", separator, value)?;
+	}
+	Ok(())
+}
+
+fn parse<T: std::str::FromStr>(text: &str, what: &str) -> Result<T, String> {
+	text.parse()
+		.map_err(|_| format!("Invalid {} <{}>", what, text))
+}
+
+fn parse_list<W: Word>(text: &str) -> Result<Vec<W>, String> {
+	if text.is_empty() {
+		return Ok(Vec::new());
+	}
+	text.split(',').map(|value| parse(value, "value")).collect()
+}
+
+impl<W: Word> MachineState<W> {
+	pub fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+		writeln!(writer, "{} {}", HEADER, STATE_VERSION)?;
+		writeln!(writer, "ip {}", self.instruction_pointer)?;
+		writeln!(writer, "base {}", self.relative_base)?;
+		writeln!(writer, "executed {}", self.instructions_executed)?;
+		writeln!(
+			writer,
+			"overflow {}",
+			overflow_policy_name(self.overflow_policy)
+		)?;
+		write!(writer, "input {} ", self.consumed_input)?;
+		write_list(&mut writer, &self.input)?;
+		write!(writer, "\noutput ")?;
+		write_list(&mut writer, &self.output)?;
+		writeln!(
+			writer,
+			"\nmemory {} {}",
+			self.memory.len(),
+			self.memory.max_size()
+		)?;
+		let mut run: Option<(usize, Vec<W>)> = None;
+		for (address, value) in self.memory.iter_nonzero() {
+			match run.as_mut() {
+				Some((start, values)) if *start + values.len() == address => values.push(value),
+				_ => {
+					if let Some((start, values)) = run.replace((address, vec![value])) {
+						write!(writer, "cells {} ", start)?;
+						write_list(&mut writer, &values)?;
+						writeln!(writer)?;
+					}
+				}
+			}
+		}
+		if let Some((start, values)) = run {
+			write!(writer, "cells {} ", start)?;
+			write_list(&mut writer, &values)?;
+			writeln!(writer)?;
+		}
+		writer.flush()
+	}
+
+	pub fn read_from(reader: impl BufRead) -> Result<MachineState<W>, StateError> {
+		let mut lines = reader.lines();
+		let header = lines.next().transpose()?.ok_or(StateError::NotAState)?;
+		match header.split_once(' ') {
+			Some((HEADER, version)) if version.trim() == STATE_VERSION.to_string() => {}
+			Some((HEADER, version)) => {
+				return Err(StateError::UnsupportedVersion(version.trim().to_string()))
+			}
+			_ => return Err(StateError::NotAState),
+		}
+		let mut state = MachineState {
+			memory: Memory::from_words(Vec::new()),
+			instruction_pointer: 0,
+			relative_base: W::zero(),
+			input: Vec::new(),
+			consumed_input: 0,
+			output: Vec::new(),
+			instructions_executed: 0,
+			overflow_policy: OverflowPolicy::default(),
+		};
+		for (i, line) in lines.enumerate() {
+			let line = line?;
+			state
+				.read_line(line.trim_end())
+				.map_err(|message| StateError::Invalid {
+					line: i + 2,
+					message,
+				})?;
+		}
+		Ok(state)
+	}
+
+	fn read_line(&mut self, line: &str) -> Result<(), String> {
+		if line.is_empty() {
+			return Ok(());
+		}
+		let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+		match key {
+			"ip" => self.instruction_pointer = parse(value, "instruction pointer")?,
+			"base" => self.relative_base = parse(value, "relative base")?,
+			"executed" => self.instructions_executed = parse(value, "instruction count")?,
+			"overflow" => {
+				self.overflow_policy = match value {
+					"checked" => OverflowPolicy::Checked,
+					"wrapping" => OverflowPolicy::Wrapping,
+					"saturating" => OverflowPolicy::Saturating,
+					_ => return Err(format!("Unknown overflow policy <{}>", value)),
+				}
+			}
+			"input" => {
+				let (consumed, values) = value.split_once(' ').unwrap_or((value, ""));
+				self.consumed_input = parse(consumed, "input count")?;
+				self.input = parse_list(values)?;
+				if self.consumed_input > self.input.len() {
+					return Err(String::from("More input consumed than there is"));
+				}
+			}
+			"output" => self.output = parse_list(value)?,
+			"memory" => {
+				let (len, max_size) = value
+					.split_once(' ')
+					.ok_or_else(|| String::from("Expected memory <len> <max_size>"))?;
+				let len = parse(len, "memory length")?;
+				let max_size = parse(max_size, "memory size")?;
+				if len > MAX_STATE_MEMORY {
+					return Err(format!(
+						"Memory length {} is above the limit of {} cells",
+						len, MAX_STATE_MEMORY
+					));
+				}
+				if len > max_size {
+					return Err(String::from("Memory length is above its maximum size"));
+				}
+				self.memory = Memory::zeroed(len, max_size);
+			}
+			"cells" => {
+				let (start, values) = value
+					.split_once(' ')
+					.ok_or_else(|| String::from("Expected cells <address> <values>"))?;
+				let start: usize = parse(start, "address")?;
+				let values: Vec<W> = parse_list(values)?;
+				if start.saturating_add(values.len()) > self.memory.len() {
+					return Err(String::from("Cells past the end of memory"));
+				}
+				for (offset, value) in values.into_iter().enumerate() {
+					self.memory.write(start + offset, value);
+				}
+			}
+			_ => return Err(format!("Unknown entry <{}>", key)),
+		}
+		Ok(())
+	}
+}
@@ -0,0 +1,91 @@
+use crate::interpreter::{Budget, ExecutionEvent, IntCodeProgram, VmError};
+use crate::word::Word;
+use futures_core::Stream;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Runs a program as a `Stream` of its outputs, awaiting input from another
+/// stream. Available with the `async` feature.
+///
+/// The program runs at most `yield_every` instructions per poll before
+/// handing control back to the executor, so long computations don't starve
+/// other tasks; a `yield_every` of 0 is treated as 1. This replaces any
+/// budget set on the program.
+///
+/// The stream is `Send` when its input stream is, so it can be spawned on a
+/// multithreaded executor.
+pub struct ProgramStream<W, I> {
+	program: IntCodeProgram<W>,
+	input: I,
+	yield_every: u64,
+	finished: bool,
+}
+
+impl<W: Word, I: Stream<Item = W> + Unpin> ProgramStream<W, I> {
+	pub fn new(program: IntCodeProgram<W>, input: I, yield_every: u64) -> ProgramStream<W, I> {
+		ProgramStream {
+			program,
+			input,
+			yield_every: yield_every.max(1),
+			finished: false,
+		}
+	}
+
+	pub fn program(&self) -> &IntCodeProgram<W> {
+		&self.program
+	}
+
+	pub fn into_inner(self) -> (IntCodeProgram<W>, I) {
+		(self.program, self.input)
+	}
+}
+
+/// Yields every output, then ends when the program halts. Errors end the
+/// stream, including `VmError::MissingInput` when the input stream ends
+/// while the program waits for input.
+impl<W: Word + Unpin, I: Stream<Item = W> + Unpin> Stream for ProgramStream<W, I> {
+	type Item = Result<W, VmError<W>>;
+
+	fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+		let this = self.get_mut();
+		if this.finished {
+			return Poll::Ready(None);
+		}
+		this.program
+			.set_budget(Budget::instructions(this.yield_every));
+		loop {
+			let event = match this.program.run_until_event() {
+				Ok(event) => event,
+				Err(error) => {
+					this.finished = true;
+					return Poll::Ready(Some(Err(error)));
+				}
+			};
+			match event {
+				ExecutionEvent::Output(value) => {
+					this.program.take_output();
+					return Poll::Ready(Some(Ok(value)));
+				}
+				ExecutionEvent::NeedsInput => match Pin::new(&mut this.input).poll_next(cx) {
+					Poll::Ready(Some(value)) => this.program.push_input(value),
+					Poll::Ready(None) => {
+						this.finished = true;
+						return Poll::Ready(Some(Err(VmError::MissingInput {
+							ip: this.program.instruction_pointer(),
+						})));
+					}
+					Poll::Pending => return Poll::Pending,
+				},
+				ExecutionEvent::Halted => {
+					this.finished = true;
+					return Poll::Ready(None);
+				}
+				ExecutionEvent::BudgetExhausted => {
+					cx.waker().wake_by_ref();
+					return Poll::Pending;
+				}
+				ExecutionEvent::Breakpoint { .. } | ExecutionEvent::Watchpoint { .. } => {}
+			}
+		}
+	}
+}
@@ -0,0 +1,100 @@
+use crate::interpreter::{ExecutionEvent, IntCodeProgram, VmError};
+use crate::word::Word;
+use std::error;
+use std::fmt;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Why a machine's thread stopped without an error.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Exit {
+	Halted,
+	/// The machine needed input, its queue was empty and every sender of its
+	/// input channel was dropped, usually because the peer feeding it halted.
+	InputClosed,
+	/// The receiver of the machine's output was dropped.
+	OutputClosed,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ThreadError<W = i64> {
+	Vm(VmError<W>),
+	/// No input arrived within the timeout given to `spawn`.
+	InputTimeout {
+		ip: usize,
+	},
+}
+
+impl<W: Word> fmt::Display for ThreadError<W> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			ThreadError::Vm(error) => error.fmt(f),
+			ThreadError::InputTimeout { ip } => write!(f, "Timed out waiting for input at {}", ip),
+		}
+	}
+}
+
+impl<W: Word> error::Error for ThreadError<W> {}
+
+impl<W> From<VmError<W>> for ThreadError<W> {
+	fn from(error: VmError<W>) -> ThreadError<W> {
+		ThreadError::Vm(error)
+	}
+}
+
+/// Runs the program returned by `build` on a new thread. `IN` blocks on
+/// `input`, for at most `input_timeout` if one is given, and every output
+/// is sent on `output` instead of being kept by the program.
+///
+/// `build` creates the program on the new thread. A program, or the memory
+/// and saved state to build one from, can be moved into it, e.g.
+/// `move || IntCodeProgram::from_memory(memory, input)`.
+/// When the machine stops, `output` is dropped, which lets a peer reading
+/// from it shut down with `Exit::InputClosed`.
+pub fn spawn<W, F>(
+	build: F,
+	input: Receiver<W>,
+	output: Sender<W>,
+	input_timeout: Option<Duration>,
+) -> JoinHandle<Result<Exit, ThreadError<W>>>
+where
+	W: Word + Send,
+	F: FnOnce() -> IntCodeProgram<W> + Send + 'static,
+{
+	thread::spawn(move || {
+		let mut program = build();
+		loop {
+			match program.run_until_event()? {
+				ExecutionEvent::Output(value) => {
+					program.take_output();
+					if output.send(value).is_err() {
+						return Ok(Exit::OutputClosed);
+					}
+				}
+				ExecutionEvent::NeedsInput => {
+					let value = match input_timeout {
+						Some(timeout) => input.recv_timeout(timeout),
+						None => input.recv().map_err(|_| RecvTimeoutError::Disconnected),
+					};
+					match value {
+						Ok(value) => program.push_input(value),
+						Err(RecvTimeoutError::Disconnected) => return Ok(Exit::InputClosed),
+						Err(RecvTimeoutError::Timeout) => {
+							return Err(ThreadError::InputTimeout {
+								ip: program.instruction_pointer(),
+							})
+						}
+					}
+				}
+				ExecutionEvent::Halted => return Ok(Exit::Halted),
+				ExecutionEvent::BudgetExhausted => {
+					return Err(ThreadError::Vm(VmError::BudgetExhausted {
+						ip: program.instruction_pointer(),
+					}))
+				}
+				ExecutionEvent::Breakpoint { .. } | ExecutionEvent::Watchpoint { .. } => {}
+			}
+		}
+	})
+}
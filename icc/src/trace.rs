@@ -0,0 +1,156 @@
+use crate::instructions::{encode_opcode, Instructions, ParameterModes};
+use crate::interpreter::{ExecutionEvent, StepInfo};
+use crate::word::Word;
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+/// Receives every instruction an `IntCodeProgram` executes, once it's set
+/// with `IntCodeProgram::set_tracer`.
+pub trait Tracer<W> {
+	/// Called after the instruction executed, including the `HLT` that halts
+	/// the program (once, however often it's resumed) but not an `IN` still
+	/// waiting for input. `step_number` counts from 0 and `relative_base` is
+	/// the base after the instruction.
+	fn trace(&mut self, step_number: u64, step: &StepInfo<W>, relative_base: &W);
+
+	/// Flushes anything buffered and reports errors raised while tracing.
+	fn finish(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+/// Lets the host keep a handle to a tracer the program owns, e.g. to call
+/// `finish` after `IntCodeProgram::run`.
+impl<W, T: Tracer<W>> Tracer<W> for Arc<Mutex<T>> {
+	fn trace(&mut self, step_number: u64, step: &StepInfo<W>, relative_base: &W) {
+		self.lock().unwrap().trace(step_number, step, relative_base);
+	}
+
+	fn finish(&mut self) -> io::Result<()> {
+		self.lock().unwrap().finish()
+	}
+}
+
+/// Passes every step to each tracer in turn.
+impl<W> Tracer<W> for Vec<Box<dyn Tracer<W> + Send>> {
+	fn trace(&mut self, step_number: u64, step: &StepInfo<W>, relative_base: &W) {
+		for tracer in self.iter_mut() {
+			tracer.trace(step_number, step, relative_base);
+		}
+	}
+
+	/// Finishes every tracer and returns the first error.
+	fn finish(&mut self) -> io::Result<()> {
+		self.iter_mut()
+			.map(|tracer| tracer.finish())
+			.fold(Ok(()), Result::and)
+	}
+}
+
+/// Writes one JSON object per executed instruction, e.g.
+///
+/// ```text
+/// {"step":3,"ip":8,"opcode":1006,"instruction":"JMPF","modes":["position","immediate"],"operands":[21,4],"values":[0,4]}
+/// ```
+///
+/// `write` is added for instructions that store a value, `rb` when the
+/// relative base changes, and `input`/`output` for I/O.
+///
+/// Writing stops at the first error, which `finish` returns.
+pub struct JsonLinesTracer<T: Write> {
+	writer: T,
+	error: Option<io::Error>,
+}
+
+impl<T: Write> JsonLinesTracer<T> {
+	pub fn new(writer: T) -> JsonLinesTracer<T> {
+		JsonLinesTracer {
+			writer,
+			error: None,
+		}
+	}
+
+	pub fn into_inner(self) -> T {
+		self.writer
+	}
+
+	/// Flushes the writer, or returns the error that stopped tracing.
+	pub fn finish(&mut self) -> io::Result<()> {
+		match self.error.take() {
+			Some(e) => Err(e),
+			None => self.writer.flush(),
+		}
+	}
+
+	fn write_record<W: Word>(
+		&mut self,
+		step_number: u64,
+		step: &StepInfo<W>,
+		relative_base: &W,
+	) -> io::Result<()> {
+		let opcode = encode_opcode(step.instruction, step.modes()).unwrap_or_default();
+		write!(
+			self.writer,
+			"{{\"step\":{},\"ip\":{},\"opcode\":{},\"instruction\":\"{}\",\"modes\":[",
+			step_number, step.address, opcode, step.instruction
+		)?;
+		for (i, mode) in step.modes().iter().enumerate() {
+			let separator = if i == 0 { "" } else { "," };
+			write!(self.writer, "{}\"{}\"", separator, mode_name(*mode))?;
+		}
+		write!(self.writer, "],\"operands\":")?;
+		write_array(&mut self.writer, step.operands())?;
+		write!(self.writer, ",\"values\":")?;
+		write_array(&mut self.writer, step.values())?;
+		if let (Some(address), Some(value)) = (step.write_address, &step.written_value) {
+			write!(
+				self.writer,
+				",\"write\":{{\"address\":{},\"value\":{}}}",
+				address, value
+			)?;
+			if step.instruction == Instructions::IN {
+				write!(self.writer, ",\"input\":{}", value)?;
+			}
+		}
+		if step.instruction == Instructions::ARB {
+			write!(self.writer, ",\"rb\":{}", relative_base)?;
+		}
+		if let Some(ExecutionEvent::Output(value)) = &step.event {
+			write!(self.writer, ",\"output\":{}", value)?;
+		}
+		writeln!(self.writer, "}}")
+	}
+}
+
+impl<W: Word, T: Write> Tracer<W> for JsonLinesTracer<T> {
+	fn trace(&mut self, step_number: u64, step: &StepInfo<W>, relative_base: &W) {
+		if self.error.is_none() {
+			if let Err(e) = self.write_record(step_number, step, relative_base) {
+				self.error = Some(e);
+			}
+		}
+	}
+
+	fn finish(&mut self) -> io::Result<()> {
+		JsonLinesTracer::finish(self)
+	}
+}
+
+fn mode_name(mode: ParameterModes) -> &'static str {
+	match mode {
+		ParameterModes::Position => "position",
+		ParameterModes::Immediate => "immediate",
+		ParameterModes::Relative => "relative",
+		ParameterModes::RelativeImmediate => "relative_immediate",
+	}
+}
+
+fn write_array<W: Display>(writer: &mut impl Write, values: &[W]) -> io::Result<()> {
+	write!(writer, "[")?;
+	for (i, value) in values.iter().enumerate() {
+		let separator = if i == 0 { "" } else { "," };
+		write!(writer, "{}{}", separator, value)?;
+	}
+	write!(writer, "]")
+}
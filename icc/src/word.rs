@@ -0,0 +1,111 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+#[cfg(feature = "bigint")]
+pub use num_bigint::BigInt;
+
+/// Value stored in a memory cell and computed on by the interpreter.
+///
+/// Implemented for `i64`, `i128` and, with the `bigint` feature, for
+/// arbitrary-precision `BigInt`. Opcodes and addresses are always small, so
+/// they only need to round-trip through `i64`.
+pub trait Word:
+	Clone + PartialEq + PartialOrd + fmt::Debug + fmt::Display + FromStr + 'static
+{
+	fn from_i64(value: i64) -> Self;
+	/// Returns `None` if the value doesn't fit in an `i64`.
+	fn to_i64(&self) -> Option<i64>;
+	fn checked_add(&self, other: &Self) -> Option<Self>;
+	fn checked_mul(&self, other: &Self) -> Option<Self>;
+	fn wrapping_add(&self, other: &Self) -> Self;
+	fn wrapping_mul(&self, other: &Self) -> Self;
+	fn saturating_add(&self, other: &Self) -> Self;
+	fn saturating_mul(&self, other: &Self) -> Self;
+
+	fn zero() -> Self {
+		Self::from_i64(0)
+	}
+
+	fn is_zero(&self) -> bool {
+		*self == Self::zero()
+	}
+}
+
+macro_rules! impl_primitive_word {
+	($t:ty) => {
+		impl Word for $t {
+			fn from_i64(value: i64) -> $t {
+				value as $t
+			}
+
+			fn to_i64(&self) -> Option<i64> {
+				i64::try_from(*self).ok()
+			}
+
+			fn checked_add(&self, other: &$t) -> Option<$t> {
+				<$t>::checked_add(*self, *other)
+			}
+
+			fn checked_mul(&self, other: &$t) -> Option<$t> {
+				<$t>::checked_mul(*self, *other)
+			}
+
+			fn wrapping_add(&self, other: &$t) -> $t {
+				<$t>::wrapping_add(*self, *other)
+			}
+
+			fn wrapping_mul(&self, other: &$t) -> $t {
+				<$t>::wrapping_mul(*self, *other)
+			}
+
+			fn saturating_add(&self, other: &$t) -> $t {
+				<$t>::saturating_add(*self, *other)
+			}
+
+			fn saturating_mul(&self, other: &$t) -> $t {
+				<$t>::saturating_mul(*self, *other)
+			}
+		}
+	};
+}
+
+impl_primitive_word!(i64);
+impl_primitive_word!(i128);
+
+/// A `BigInt` never overflows, so every overflow policy computes the exact
+/// result.
+#[cfg(feature = "bigint")]
+impl Word for BigInt {
+	fn from_i64(value: i64) -> BigInt {
+		BigInt::from(value)
+	}
+
+	fn to_i64(&self) -> Option<i64> {
+		i64::try_from(self).ok()
+	}
+
+	fn checked_add(&self, other: &BigInt) -> Option<BigInt> {
+		Some(self + other)
+	}
+
+	fn checked_mul(&self, other: &BigInt) -> Option<BigInt> {
+		Some(self * other)
+	}
+
+	fn wrapping_add(&self, other: &BigInt) -> BigInt {
+		self + other
+	}
+
+	fn wrapping_mul(&self, other: &BigInt) -> BigInt {
+		self * other
+	}
+
+	fn saturating_add(&self, other: &BigInt) -> BigInt {
+		self + other
+	}
+
+	fn saturating_mul(&self, other: &BigInt) -> BigInt {
+		self * other
+	}
+}
@@ -0,0 +1,60 @@
+use icc::ascii::{decode, encode_line, AsciiIo};
+use icc::interpreter::{ExecutionEvent, IntCodeProgram};
+use icc::Memory;
+
+/// Echoes a line of input, then prints 1000 and halts.
+const ECHO_LINE: &[i64] = &[
+	3, 20, // 0: IN -> 20
+	4, 20, // 2: OUT [20]
+	1008, 20, 10, 21, // 4: EQ [20] 10 -> 21
+	1006, 21, 0, // 8: JMPF [21] 0
+	104, 1000, // 11: OUT 1000
+	99, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+
+fn load(cells: &[i64]) -> IntCodeProgram {
+	IntCodeProgram::from_memory(Memory::new(cells.to_vec()), Vec::new())
+}
+
+#[test]
+fn lines_are_encoded_with_a_newline() {
+	assert_eq!(encode_line::<i64>("Hi!"), vec![72, 105, 33, 10]);
+	assert_eq!(encode_line::<i64>(""), vec![10]);
+}
+
+#[test]
+fn only_ascii_values_decode() {
+	assert_eq!(decode(&65i64), Some('A'));
+	assert_eq!(decode(&10i64), Some('\n'));
+	assert_eq!(decode(&127i64), Some('\x7f'));
+	assert_eq!(decode(&128i64), None);
+	assert_eq!(decode(&-1i64), None);
+}
+
+#[test]
+fn programs_are_driven_by_lines_of_text() {
+	let mut program = load(ECHO_LINE);
+	let mut ascii = AsciiIo::new(&mut program);
+	let mut text = String::new();
+	assert_eq!(
+		ascii.run_until_event(|s| text.push_str(s)),
+		Ok(ExecutionEvent::NeedsInput)
+	);
+	assert_eq!(text, "");
+	ascii.push_line("hello");
+	assert_eq!(
+		ascii.run_until_event(|s| text.push_str(s)),
+		Ok(ExecutionEvent::Halted)
+	);
+	assert_eq!(text, "hello\n1000\n");
+	assert_eq!(ascii.program().output().len(), 7);
+}
+
+#[test]
+fn numbers_in_the_middle_of_a_line_get_their_own_line() {
+	let mut program = load(&[104, 97, 104, 500, 104, 98, 104, 10, 104, 600, 99]);
+	let mut text = String::new();
+	let event = AsciiIo::new(&mut program).run_until_event(|s| text.push_str(s));
+	assert_eq!(event, Ok(ExecutionEvent::Halted));
+	assert_eq!(text, "a\n500\nb\n600\n");
+}
@@ -0,0 +1,231 @@
+use icc::interpreter::{Access, Breakpoint, Condition, ExecutionEvent, IntCodeProgram, Watchpoint};
+use icc::Memory;
+
+/// Counts cell 20 up from 0 to 3, outputting every value.
+const COUNT: &[i64] = &[
+	1001, 20, 1, 20, // 0: ADD [20] 1 -> 20
+	4, 20, // 4: OUT [20]
+	1007, 20, 3, 21, // 6: LESS [20] 3 -> 21
+	1005, 21, 0, // 10: JMP [21] 0
+	99, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+
+fn load(cells: &[i64]) -> IntCodeProgram {
+	IntCodeProgram::from_memory(Memory::new(cells.to_vec()), Vec::new())
+}
+
+/// Runs until the program halts and returns every event on the way.
+fn events(program: &mut IntCodeProgram) -> Vec<ExecutionEvent> {
+	let mut events = Vec::new();
+	loop {
+		match program.run_until_event().unwrap() {
+			ExecutionEvent::Halted => return events,
+			event => events.push(event),
+		}
+	}
+}
+
+#[test]
+fn address_breakpoints_stop_before_the_instruction() {
+	let mut program = load(COUNT);
+	let id = program.add_breakpoint(Breakpoint::at(4));
+	assert_eq!(
+		program.run_until_event(),
+		Ok(ExecutionEvent::Breakpoint { id, ip: 4 })
+	);
+	assert_eq!(program.instruction_pointer(), 4);
+	assert_eq!(program.output(), &[] as &[i64]);
+	// Resuming runs the instruction instead of stopping on it again.
+	assert_eq!(program.run_until_event(), Ok(ExecutionEvent::Output(1)));
+	assert_eq!(
+		events(&mut program),
+		vec![
+			ExecutionEvent::Breakpoint { id, ip: 4 },
+			ExecutionEvent::Output(2),
+			ExecutionEvent::Breakpoint { id, ip: 4 },
+			ExecutionEvent::Output(3),
+		]
+	);
+}
+
+#[test]
+fn breakpoints_on_input_stop_once_while_waiting_for_it() {
+	// IN 5, OUT [5], HLT
+	let mut program = load(&[3, 5, 4, 5, 99, 0]);
+	let id = program.add_breakpoint(Breakpoint::at(0));
+	assert_eq!(
+		program.run_until_event(),
+		Ok(ExecutionEvent::Breakpoint { id, ip: 0 })
+	);
+	assert_eq!(program.run_until_event(), Ok(ExecutionEvent::NeedsInput));
+	program.push_input(7);
+	assert_eq!(program.run_until_event(), Ok(ExecutionEvent::Output(7)));
+}
+
+#[test]
+fn condition_breakpoints_stop_while_the_condition_holds() {
+	let mut program = load(COUNT);
+	let id = program.add_breakpoint(Breakpoint::when(Condition::Memory {
+		address: 20,
+		value: 2,
+	}));
+	assert_eq!(
+		events(&mut program),
+		vec![
+			ExecutionEvent::Output(1),
+			ExecutionEvent::Breakpoint { id, ip: 4 },
+			ExecutionEvent::Output(2),
+			ExecutionEvent::Breakpoint { id, ip: 6 },
+			ExecutionEvent::Breakpoint { id, ip: 10 },
+			ExecutionEvent::Breakpoint { id, ip: 0 },
+			ExecutionEvent::Output(3),
+		]
+	);
+}
+
+#[test]
+fn conditional_address_breakpoints_need_both() {
+	let mut program = load(COUNT);
+	let id = program.add_breakpoint(Breakpoint::at_if(
+		0,
+		Condition::Memory {
+			address: 20,
+			value: 2,
+		},
+	));
+	assert_eq!(
+		events(&mut program),
+		vec![
+			ExecutionEvent::Output(1),
+			ExecutionEvent::Output(2),
+			ExecutionEvent::Breakpoint { id, ip: 0 },
+			ExecutionEvent::Output(3),
+		]
+	);
+}
+
+#[test]
+fn breakpoints_can_wait_for_the_relative_base() {
+	let mut program = load(&[109, 5, 109, 5, 109, 5, 99]);
+	let id = program.add_breakpoint(Breakpoint::when(Condition::RelativeBase(10)));
+	assert_eq!(
+		program.run_until_event(),
+		Ok(ExecutionEvent::Breakpoint { id, ip: 4 })
+	);
+	assert_eq!(program.relative_base(), &10);
+	assert_eq!(program.run_until_event(), Ok(ExecutionEvent::Halted));
+	assert_eq!(program.relative_base(), &15);
+}
+
+#[test]
+fn watchpoints_stop_after_the_access() {
+	let mut program = load(COUNT);
+	let id = program.add_watchpoint(Watchpoint::read(21));
+	assert_eq!(program.run_until_event(), Ok(ExecutionEvent::Output(1)));
+	// `LESS` writes cell 21, which a read watchpoint ignores; the jump reads it.
+	assert_eq!(
+		program.run_until_event(),
+		Ok(ExecutionEvent::Watchpoint {
+			id,
+			ip: 10,
+			address: 21,
+			access: Access::Read,
+		})
+	);
+	assert_eq!(program.instruction_pointer(), 0);
+}
+
+#[test]
+fn write_watchpoints_ignore_reads() {
+	let mut program = load(COUNT);
+	let id = program.add_watchpoint(Watchpoint::write(20));
+	let watch = ExecutionEvent::Watchpoint {
+		id,
+		ip: 0,
+		address: 20,
+		access: Access::Write,
+	};
+	assert_eq!(
+		events(&mut program),
+		vec![
+			watch,
+			ExecutionEvent::Output(1),
+			watch,
+			ExecutionEvent::Output(2),
+			watch,
+			ExecutionEvent::Output(3),
+		]
+	);
+	assert_eq!(program.memory().read(20), 3);
+}
+
+#[test]
+fn watchpoints_are_reported_after_the_instruction_event() {
+	let mut program = load(COUNT);
+	let id = program.add_watchpoint(Watchpoint::access(20));
+	let read_at = |ip| ExecutionEvent::Watchpoint {
+		id,
+		ip,
+		address: 20,
+		access: Access::Read,
+	};
+	assert_eq!(program.run_until_event(), Ok(read_at(0)));
+	assert_eq!(program.run_until_event(), Ok(ExecutionEvent::Output(1)));
+	assert_eq!(program.run_until_event(), Ok(read_at(4)));
+	assert_eq!(program.run_until_event(), Ok(read_at(6)));
+}
+
+#[test]
+fn fetching_an_instruction_is_not_an_access() {
+	let mut program = load(COUNT);
+	program.add_watchpoint(Watchpoint::access(0));
+	program.add_watchpoint(Watchpoint::access(1));
+	assert_eq!(
+		events(&mut program),
+		vec![
+			ExecutionEvent::Output(1),
+			ExecutionEvent::Output(2),
+			ExecutionEvent::Output(3),
+		]
+	);
+}
+
+#[test]
+fn breakpoints_can_be_listed_and_removed() {
+	let mut program = load(COUNT);
+	let breakpoint = program.add_breakpoint(Breakpoint::at(4));
+	let watchpoint = program.add_watchpoint(Watchpoint::write(20));
+	assert_ne!(breakpoint, watchpoint);
+	assert_eq!(
+		program.breakpoints().collect::<Vec<_>>(),
+		vec![(breakpoint, &Breakpoint::at(4))]
+	);
+	assert_eq!(
+		program.watchpoints().collect::<Vec<_>>(),
+		vec![(watchpoint, &Watchpoint::write(20))]
+	);
+
+	assert!(program.remove_breakpoint(breakpoint));
+	assert!(!program.remove_breakpoint(breakpoint));
+	assert_eq!(program.breakpoints().count(), 0);
+	assert_eq!(program.watchpoints().count(), 1);
+
+	program.clear_breakpoints();
+	assert_eq!(program.watchpoints().count(), 0);
+	assert_eq!(
+		events(&mut program),
+		vec![
+			ExecutionEvent::Output(1),
+			ExecutionEvent::Output(2),
+			ExecutionEvent::Output(3),
+		]
+	);
+}
+
+#[test]
+fn run_does_not_stop_at_breakpoints() {
+	let mut program = load(COUNT);
+	program.add_breakpoint(Breakpoint::at(4));
+	program.add_watchpoint(Watchpoint::access(20));
+	assert_eq!(program.run(), Ok(vec![1, 2, 3]));
+}
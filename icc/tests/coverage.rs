@@ -0,0 +1,114 @@
+use icc::coverage::Coverage;
+use icc::instructions::Instructions;
+use icc::interpreter::{ExecutionEvent, IntCodeProgram};
+use icc::source_map::SourceMap;
+use icc::Memory;
+use std::sync::{Arc, Mutex};
+
+/// Counts cell 20 up from 0 to 3, outputting every value. The `JMPF` after
+/// the `HLT` never runs.
+const COUNT: &[i64] = &[
+	1001, 20, 1, 20, 4, 20, 1007, 20, 3, 21, 1005, 21, 0, 99, 1106, 0, 13, 0, 0, 0, 0, 0,
+];
+
+/// Runs `COUNT` until it halts and returns its coverage.
+fn cover() -> Coverage {
+	let coverage = Arc::new(Mutex::new(Coverage::new()));
+	let mut program = IntCodeProgram::from_memory(Memory::new(COUNT.to_vec()), Vec::new());
+	program.set_tracer(coverage.clone());
+	assert_eq!(program.run(), Ok(vec![1, 2, 3]));
+	let coverage = coverage.lock().unwrap().clone();
+	coverage
+}
+
+/// Source map of `COUNT` as the assembler would build it.
+fn source_map() -> SourceMap {
+	let lines = [
+		"; counts to 3",
+		"loop: add [counter], 1, counter",
+		"out [counter]",
+		"less [counter], 3, more",
+		"jmp [more], loop",
+		"hlt",
+		"jmpf 0, end",
+	];
+	let mut map = SourceMap::new(
+		String::from("count.s"),
+		lines.iter().map(|line| line.to_string()).collect(),
+	);
+	map.add_instruction(0, Instructions::ADD, 4, 2);
+	map.add_instruction(4, Instructions::OUT, 2, 3);
+	map.add_instruction(6, Instructions::LESS, 4, 4);
+	map.add_instruction(10, Instructions::JMP, 3, 5);
+	map.add_instruction(13, Instructions::HLT, 1, 6);
+	map.add_instruction(14, Instructions::JMPF, 3, 7);
+	map
+}
+
+#[test]
+fn executions_and_branches_are_recorded() {
+	let coverage = cover();
+	assert_eq!(coverage.executions(0), 3);
+	assert_eq!(coverage.executions(13), 1);
+	assert_eq!(coverage.executions(14), 0);
+	assert_eq!(coverage.branch(10), Some((2, 1)));
+	assert_eq!(coverage.branch(14), None);
+	assert_eq!(coverage.branch(0), None);
+}
+
+#[test]
+fn reports_mark_lines_that_never_ran() {
+	let expected = "Coverage of count.s: 5/6 lines, 2/4 branches
+          |    1: ; counts to 3
+        3 |    2: loop: add [counter], 1, counter
+        3 |    3: out [counter]
+        3 |    4: less [counter], 3, more
+        3 |    5: jmp [more], loop  [jumped 2, fell through 1]
+        1 |    6: hlt
+    ##### |    7: jmpf 0, end  [jumped 0, fell through 0]
+";
+	assert_eq!(cover().report(&source_map()), expected);
+}
+
+#[test]
+fn lcov_files_list_lines_and_branches() {
+	let expected = "TN:
+SF:count.s
+BRDA:5,0,0,2
+BRDA:5,0,1,1
+BRDA:7,0,0,-
+BRDA:7,0,1,-
+BRF:4
+BRH:2
+DA:2,3
+DA:3,3
+DA:4,3
+DA:5,3
+DA:6,1
+DA:7,0
+LF:6
+LH:5
+end_of_record
+";
+	let mut lcov = Vec::new();
+	cover().write_lcov(&source_map(), &mut lcov).unwrap();
+	assert_eq!(String::from_utf8(lcov).unwrap(), expected);
+}
+
+#[test]
+fn resuming_a_halted_program_does_not_count_the_halt_again() {
+	let coverage = Arc::new(Mutex::new(Coverage::new()));
+	let mut program = IntCodeProgram::from_memory(Memory::new(COUNT.to_vec()), Vec::new());
+	program.set_tracer(coverage.clone());
+	while program.run_until_event() != Ok(ExecutionEvent::Halted) {}
+	for _ in 0..3 {
+		assert_eq!(program.run_until_event(), Ok(ExecutionEvent::Halted));
+		program.step().unwrap();
+	}
+	assert_eq!(coverage.lock().unwrap().executions(13), 1);
+
+	// Moving the program off the `HLT` and back makes it a new halt.
+	program.set_instruction_pointer(13);
+	program.run_until_event().unwrap();
+	assert_eq!(coverage.lock().unwrap().executions(13), 2);
+}
@@ -0,0 +1,126 @@
+use icc::instructions::{
+	decode, decode_opcode, encode, encode_opcode, Instructions, Operand, ParameterModes,
+	INSTRUCTION_SET,
+};
+use icc::interpreter::IntCodeProgram;
+use icc::Memory;
+use proptest::prelude::*;
+use proptest::sample;
+
+const READ_MODES: &[ParameterModes] = &[
+	ParameterModes::Position,
+	ParameterModes::Immediate,
+	ParameterModes::Relative,
+];
+const WRITE_MODES: &[ParameterModes] =
+	&[ParameterModes::Immediate, ParameterModes::RelativeImmediate];
+
+/// Any instruction with valid modes for its parameters and arbitrary
+/// operand values.
+fn instruction() -> impl Strategy<Value = (Instructions, Vec<Operand<i64>>)> {
+	let instructions: Vec<_> = INSTRUCTION_SET.iter().flatten().collect();
+	sample::select(instructions).prop_flat_map(|data| {
+		let operands: Vec<_> = (0..data.arguments_count)
+			.map(|argument| {
+				let modes = if data.write_argument == Some(argument) {
+					WRITE_MODES
+				} else {
+					READ_MODES
+				};
+				(sample::select(modes), any::<i64>())
+					.prop_map(|(mode, value)| Operand::new(mode, value))
+			})
+			.collect();
+		(Just(data.instruction), operands)
+	})
+}
+
+proptest! {
+	#[test]
+	fn encoded_instructions_decode_to_the_same_instruction(
+		(instruction, operands) in instruction(),
+		address in 0usize..5000,
+	) {
+		let cells = encode(instruction, &operands).unwrap();
+		let mut memory = Memory::new(vec![0; address]);
+		for (offset, cell) in cells.iter().enumerate() {
+			memory.write(address + offset, *cell);
+		}
+		let decoded = decode(&memory, address).unwrap();
+		prop_assert_eq!(decoded.address, address);
+		prop_assert_eq!(decoded.instruction, instruction);
+		prop_assert_eq!(decoded.size(), cells.len());
+		prop_assert_eq!(decoded.operands, operands);
+	}
+
+	#[test]
+	fn decoded_words_encode_to_the_same_word(word in prop_oneof![0i64..100_000, any::<i64>()]) {
+		if let Ok(opcode) = decode_opcode(&word) {
+			prop_assert_eq!(encode_opcode(opcode.instruction, opcode.modes()), Ok(word));
+		}
+	}
+
+	#[test]
+	fn the_interpreter_decodes_like_decode((instruction, operands) in instruction()) {
+		let cells = encode(instruction, &operands).unwrap();
+		let memory = Memory::new(cells);
+		let decoded = decode(&memory, 0).unwrap();
+		let mut program = IntCodeProgram::from_memory(memory, vec![1]);
+		// Execution may fail on the operand values, but not before decoding.
+		if let Ok(step) = program.step() {
+			prop_assert_eq!(step.instruction, decoded.instruction);
+			let modes: Vec<_> = decoded.operands.iter().map(|operand| operand.mode).collect();
+			let values: Vec<_> = decoded.operands.iter().map(|operand| operand.value).collect();
+			prop_assert_eq!(step.modes(), &modes[..]);
+			prop_assert_eq!(step.operands(), &values[..]);
+		}
+	}
+}
+
+/// Every instruction word below 100000, which covers every mode digit of
+/// every instruction.
+#[test]
+fn decodable_words_encode_to_the_same_word() {
+	for word in 0..100_000i64 {
+		if let Ok(opcode) = decode_opcode(&word) {
+			assert_eq!(encode_opcode(opcode.instruction, opcode.modes()), Ok(word));
+		}
+	}
+}
+
+#[test]
+fn instruction_set_entries_sit_at_their_opcode() {
+	let mut count = 0;
+	for (opc, data) in INSTRUCTION_SET.iter().enumerate() {
+		let data = match data {
+			Some(data) => data,
+			None => {
+				assert!(Instructions::get_instruction_from_opc(opc).is_err());
+				continue;
+			}
+		};
+		count += 1;
+		assert_eq!(data.instruction.get_instruction_opc(), opc);
+		assert_eq!(
+			Instructions::get_instruction_from_opc(opc),
+			Ok(data.instruction)
+		);
+		assert_eq!(data.instruction.data(), data);
+		assert_eq!(data.arguments_count as usize, data.default_modes.len());
+		assert!(data
+			.write_argument
+			.is_none_or(|argument| argument < data.arguments_count));
+		let name = data.instruction.to_string();
+		assert_eq!(
+			Instructions::get_instruction_from_name(&name),
+			Some(data.instruction)
+		);
+		assert_eq!(
+			Instructions::get_instruction_from_name(&name.to_lowercase()),
+			Some(data.instruction)
+		);
+	}
+	assert_eq!(count, 12);
+	assert!(Instructions::get_instruction_from_opc(100).is_err());
+	assert_eq!(Instructions::get_instruction_from_name("nop"), None);
+}
@@ -0,0 +1,149 @@
+use icc::interpreter::{Breakpoint, Condition, ExecutionEvent, IntCodeProgram};
+use icc::Memory;
+
+/// Moves the relative base, reads two values and outputs their sum.
+const SUM: &[i64] = &[
+	109, 5, // 0: ARB 5
+	203, 10, // 2: IN -> base + 10
+	3, 16, // 4: IN -> 16
+	1, 15, 16, 17, // 6: ADD [15] [16] -> 17
+	4, 17, // 10: OUT [17]
+	99, 0, 0, 0, 0, 0,
+];
+
+/// Counts cell 20 up forever, two steps per count.
+const COUNTER: &[i64] = &[
+	1001, 20, 1, 20, 1105, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+
+fn load(cells: &[i64], input: Vec<i64>) -> IntCodeProgram {
+	IntCodeProgram::from_memory(Memory::new(cells.to_vec()), input)
+}
+
+/// Everything stepping back is expected to restore.
+#[derive(PartialEq, Debug)]
+struct Snapshot {
+	ip: usize,
+	base: i64,
+	memory: Vec<i64>,
+	pending_input: Vec<i64>,
+	output: Vec<i64>,
+	executed: u64,
+}
+
+fn snapshot(program: &IntCodeProgram) -> Snapshot {
+	Snapshot {
+		ip: program.instruction_pointer(),
+		base: *program.relative_base(),
+		memory: program.memory().read_range(0..32),
+		pending_input: program.pending_input().to_vec(),
+		output: program.output().to_vec(),
+		executed: program.instructions_executed(),
+	}
+}
+
+#[test]
+fn step_back_restores_every_step() {
+	let mut program = load(SUM, vec![3, 4]);
+	program.set_history(Some(100));
+	let mut snapshots = vec![snapshot(&program)];
+	while program.run_until_event().unwrap() != ExecutionEvent::Halted {}
+	assert_eq!(program.output(), &[7]);
+
+	// Replay to get the state after each step.
+	let mut replay = load(SUM, vec![3, 4]);
+	for _ in 0..5 {
+		replay.step().unwrap();
+		snapshots.push(snapshot(&replay));
+	}
+	assert_eq!(program.history_len(), 5);
+	while let Some(expected) = snapshots.pop() {
+		assert_eq!(snapshot(&program), expected);
+		let reverted = program.step_back(1);
+		assert_eq!(reverted, if snapshots.is_empty() { 0 } else { 1 });
+	}
+	assert_eq!(program.history_len(), 0);
+}
+
+#[test]
+fn a_reverted_program_runs_again() {
+	let mut program = load(SUM, vec![3, 4]);
+	program.set_history(Some(100));
+	program.run_until_event().unwrap();
+	assert_eq!(program.step_back(3), 3);
+	assert_eq!(program.pending_input(), &[4]);
+	assert_eq!(program.run_until_event(), Ok(ExecutionEvent::Output(7)));
+	assert_eq!(program.output(), &[7]);
+}
+
+#[test]
+fn step_back_over_checkpoints_restores_the_state() {
+	let mut program = load(COUNTER, Vec::new());
+	program.set_history(Some(20_000));
+	for _ in 0..101 {
+		program.step().unwrap();
+	}
+	let expected = snapshot(&program);
+	for _ in 0..10_000 {
+		program.step().unwrap();
+	}
+	assert_eq!(program.memory().read(20), 5051);
+	assert_eq!(program.step_back(10_000), 10_000);
+	assert_eq!(snapshot(&program), expected);
+	assert_eq!(program.history_len(), 101);
+	assert_eq!(program.step_back(200), 101);
+	assert_eq!(snapshot(&program), snapshot(&load(COUNTER, Vec::new())));
+}
+
+#[test]
+fn history_keeps_at_least_the_requested_steps() {
+	let mut program = load(COUNTER, Vec::new());
+	program.set_history(Some(5000));
+	for _ in 0..50_000 {
+		program.step().unwrap();
+	}
+	let kept = program.history_len();
+	assert!((5000..50_000).contains(&kept), "kept {} steps", kept);
+	assert_eq!(program.step_back(50_000), kept);
+	assert_eq!(program.instructions_executed(), 50_000 - kept as u64);
+	assert_eq!(
+		program.memory().read(20),
+		program.instructions_executed() as i64 / 2
+	);
+}
+
+#[test]
+fn run_back_to_stops_where_the_breakpoint_matches() {
+	let mut program = load(COUNTER, Vec::new());
+	program.set_history(Some(1000));
+	for _ in 0..100 {
+		program.step().unwrap();
+	}
+	let breakpoint = Breakpoint::at_if(
+		4,
+		Condition::Memory {
+			address: 20,
+			value: 10,
+		},
+	);
+	assert!(program.run_back_to(&breakpoint));
+	assert_eq!(program.instruction_pointer(), 4);
+	assert_eq!(program.memory().read(20), 10);
+	assert_eq!(program.instructions_executed(), 19);
+
+	assert!(!program.run_back_to(&Breakpoint::at(7)));
+	assert_eq!(program.instructions_executed(), 0);
+	assert_eq!(program.memory().read(20), 0);
+}
+
+#[test]
+fn nothing_is_reverted_without_history() {
+	let mut program = load(SUM, vec![3, 4]);
+	program.run_until_event().unwrap();
+	assert_eq!(program.step_back(1), 0);
+	assert_eq!(program.output(), &[7]);
+
+	program.set_history(Some(10));
+	program.set_history(None);
+	assert_eq!(program.history_len(), 0);
+}
@@ -0,0 +1,411 @@
+use icc::instructions::{Instructions, ParameterModes};
+use icc::interpreter::{Budget, ExecutionEvent, IntCodeProgram, OverflowPolicy, VmError};
+use icc::Memory;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Reads two values, outputs their sum and halts.
+const SUM: &[i64] = &[3, 13, 3, 14, 1, 13, 14, 15, 4, 15, 99, 0, 0, 0, 0, 0];
+
+/// Jumps to itself forever.
+const LOOP: &[i64] = &[1105, 1, 0];
+
+fn load(cells: &[i64], input: Vec<i64>) -> IntCodeProgram {
+	IntCodeProgram::from_memory(Memory::new(cells.to_vec()), input)
+}
+
+#[test]
+fn handles_can_capture_state() {
+	let outputs = Arc::new(Mutex::new(Vec::new()));
+	let sink = outputs.clone();
+	let mut queue = vec![5, 4];
+	let mut program = IntCodeProgram::new(
+		Memory::new(SUM.to_vec()),
+		Vec::new(),
+		move |value| sink.lock().unwrap().push(value),
+		move || queue.pop().unwrap(),
+	);
+	assert_eq!(program.run(), Ok(vec![9]));
+	assert_eq!(*outputs.lock().unwrap(), vec![9]);
+}
+
+#[test]
+fn queued_input_is_read_before_the_input_handle() {
+	let calls = Arc::new(Mutex::new(0));
+	let counter = calls.clone();
+	let mut program = IntCodeProgram::new(
+		Memory::new(SUM.to_vec()),
+		vec![40],
+		|_| {},
+		move || {
+			*counter.lock().unwrap() += 1;
+			2
+		},
+	);
+	assert_eq!(program.run(), Ok(vec![42]));
+	assert_eq!(*calls.lock().unwrap(), 1);
+}
+
+#[test]
+fn run_until_event_pauses_for_input_and_resumes() {
+	let mut program = load(SUM, Vec::new());
+	assert_eq!(program.run_until_event(), Ok(ExecutionEvent::NeedsInput));
+	assert_eq!(program.instruction_pointer(), 0);
+	// Asking again without input doesn't move the program.
+	assert_eq!(program.run_until_event(), Ok(ExecutionEvent::NeedsInput));
+	program.push_input(20);
+	assert_eq!(program.run_until_event(), Ok(ExecutionEvent::NeedsInput));
+	assert_eq!(program.instruction_pointer(), 2);
+	program.push_input(22);
+	assert_eq!(program.run_until_event(), Ok(ExecutionEvent::Output(42)));
+	assert_eq!(program.run_until_event(), Ok(ExecutionEvent::Halted));
+	assert_eq!(program.run_until_event(), Ok(ExecutionEvent::Halted));
+	assert_eq!(program.output(), &[42]);
+}
+
+#[test]
+fn every_output_is_its_own_event() {
+	// Outputs 1, 2 and 3 and halts.
+	let mut program = load(&[104, 1, 104, 2, 104, 3, 99], Vec::new());
+	let mut events = Vec::new();
+	loop {
+		match program.run_until_event().unwrap() {
+			ExecutionEvent::Halted => break,
+			event => events.push(event),
+		}
+	}
+	assert_eq!(
+		events,
+		vec![
+			ExecutionEvent::Output(1),
+			ExecutionEvent::Output(2),
+			ExecutionEvent::Output(3)
+		]
+	);
+	assert_eq!(program.take_output(), vec![1, 2, 3]);
+	assert!(program.output().is_empty());
+}
+
+#[test]
+fn run_fails_when_input_runs_out() {
+	let mut program = load(SUM, vec![1]);
+	assert_eq!(program.run(), Err(VmError::MissingInput { ip: 2 }));
+	program.push_input(2);
+	assert_eq!(program.run(), Ok(vec![3]));
+}
+
+#[test]
+fn unknown_opcodes_are_errors() {
+	assert_eq!(
+		load(&[42], Vec::new()).run(),
+		Err(VmError::UnknownOpcode { opcode: 42, ip: 0 })
+	);
+	assert_eq!(
+		load(&[-1], Vec::new()).run(),
+		Err(VmError::UnknownOpcode { opcode: -1, ip: 0 })
+	);
+}
+
+#[test]
+fn invalid_modes_are_errors() {
+	// Mode 3 doesn't exist.
+	assert_eq!(
+		load(&[304, 0, 99], Vec::new()).run(),
+		Err(VmError::InvalidMode { mode: 3, ip: 0 })
+	);
+	// `OUT` has a single parameter, so only one mode digit.
+	assert_eq!(
+		load(&[10104, 0, 99], Vec::new()).run(),
+		Err(VmError::InvalidMode { mode: 0, ip: 0 })
+	);
+}
+
+#[test]
+fn negative_addresses_are_errors() {
+	assert_eq!(
+		load(&[4, -1, 99], Vec::new()).run(),
+		Err(VmError::NegativeAddress { addr: -1, ip: 0 })
+	);
+	// Relative base -10 plus 3.
+	assert_eq!(
+		load(&[109, -10, 204, 3, 99], Vec::new()).run(),
+		Err(VmError::NegativeAddress { addr: -7, ip: 2 })
+	);
+	assert_eq!(
+		load(&[1105, 1, -5], Vec::new()).run(),
+		Err(VmError::NegativeAddress { addr: -5, ip: 0 })
+	);
+}
+
+#[test]
+fn writes_past_the_memory_size_are_errors() {
+	let memory = Memory::with_max_size(vec![1101, 1, 1, 100, 99], 10);
+	let mut program: IntCodeProgram = IntCodeProgram::from_memory(memory, Vec::new());
+	assert_eq!(
+		program.run(),
+		Err(VmError::AddressOutOfRange { addr: 100, ip: 0 })
+	);
+}
+
+#[test]
+fn writes_to_immediate_parameters_are_errors() {
+	assert_eq!(
+		load(&[11101, 1, 1, 5, 99], Vec::new()).run(),
+		Err(VmError::WriteToImmediate { ip: 0 })
+	);
+}
+
+#[test]
+fn a_failed_instruction_leaves_the_machine_on_it() {
+	let mut program = load(&[104, 7, 1101, 1, 1, -1, 99], Vec::new());
+	assert_eq!(program.run_until_event(), Ok(ExecutionEvent::Output(7)));
+	let error = program.run_until_event().unwrap_err();
+	assert_eq!(error, VmError::NegativeAddress { addr: -1, ip: 2 });
+	assert_eq!(error.to_string(), "Negative address -1 accessed at 2");
+	assert_eq!(program.instruction_pointer(), 2);
+	assert_eq!(program.instructions_executed(), 1);
+	// Retrying fails the same way.
+	assert_eq!(program.run_until_event(), Err(error));
+}
+
+#[test]
+fn step_describes_the_executed_instruction() {
+	// ADD mem[6] + 5 into 7.
+	let mut program = load(&[1001, 6, 5, 7, 99, 0, 37, 0], Vec::new());
+	let step = program.step().unwrap();
+	assert_eq!(step.address, 0);
+	assert_eq!(step.instruction, Instructions::ADD);
+	assert_eq!(
+		step.modes(),
+		&[
+			ParameterModes::Position,
+			ParameterModes::Immediate,
+			ParameterModes::Immediate
+		]
+	);
+	assert_eq!(step.operands(), &[6, 5, 7]);
+	assert_eq!(step.values(), &[37, 5, 7]);
+	assert_eq!(step.read_addresses().collect::<Vec<_>>(), vec![6]);
+	assert_eq!(step.write_address, Some(7));
+	assert_eq!(step.written_value, Some(42));
+	assert_eq!(step.instruction_pointer, 4);
+	assert_eq!(step.event, None);
+	assert_eq!(program.memory().read(7), 42);
+	assert_eq!(program.instruction_pointer(), 4);
+	assert_eq!(program.instructions_executed(), 1);
+
+	let step = program.step().unwrap();
+	assert_eq!(step.instruction, Instructions::HLT);
+	assert_eq!(step.event, Some(ExecutionEvent::Halted));
+	assert_eq!(step.instruction_pointer, 4);
+	assert_eq!(program.instructions_executed(), 1);
+}
+
+#[test]
+fn step_resolves_relative_parameters() {
+	// ARB 10, then OUT mem[base - 3].
+	let mut program = load(&[109, 10, 204, -3, 99, 0, 0, 55], Vec::new());
+	program.step().unwrap();
+	assert_eq!(*program.relative_base(), 10);
+	let step = program.step().unwrap();
+	assert_eq!(step.modes(), &[ParameterModes::Relative]);
+	assert_eq!(step.operands(), &[-3]);
+	assert_eq!(step.values(), &[55]);
+	assert_eq!(step.read_addresses().collect::<Vec<_>>(), vec![7]);
+	assert_eq!(step.write_address, None);
+	assert_eq!(step.event, Some(ExecutionEvent::Output(55)));
+}
+
+#[test]
+fn step_without_input_stays_on_the_instruction() {
+	let mut program = load(SUM, Vec::new());
+	let step = program.step().unwrap();
+	assert_eq!(step.event, Some(ExecutionEvent::NeedsInput));
+	assert_eq!(step.instruction_pointer, 0);
+	assert_eq!(step.write_address, None);
+	assert_eq!(program.instruction_pointer(), 0);
+	assert_eq!(program.instructions_executed(), 0);
+	program.push_input(3);
+	let step = program.step().unwrap();
+	assert_eq!(step.event, None);
+	assert_eq!(step.write_address, Some(13));
+	assert_eq!(step.written_value, Some(3));
+	assert_eq!(program.instruction_pointer(), 2);
+}
+
+#[test]
+fn machine_state_can_be_changed_between_runs() {
+	// OUT mem[base + 0], then halt.
+	let mut program = load(&[204, 0, 99, 11, 22], Vec::new());
+	program.set_relative_base(4);
+	assert_eq!(program.run_until_event(), Ok(ExecutionEvent::Output(22)));
+	program.set_instruction_pointer(0);
+	program.set_relative_base(3);
+	assert_eq!(program.run_until_event(), Ok(ExecutionEvent::Output(11)));
+	program.set_instruction_pointer(0);
+	program.memory_mut().write(3, 33);
+	assert_eq!(program.run_until_event(), Ok(ExecutionEvent::Output(33)));
+	assert_eq!(program.output(), &[22, 11, 33]);
+}
+
+#[test]
+fn pending_input_only_holds_unread_values() {
+	let mut program = load(SUM, vec![1, 2, 3]);
+	assert_eq!(program.pending_input(), &[1, 2, 3]);
+	program.step().unwrap();
+	assert_eq!(program.pending_input(), &[2, 3]);
+	program.clear_pending_input();
+	assert!(program.pending_input().is_empty());
+	assert_eq!(program.run_until_event(), Ok(ExecutionEvent::NeedsInput));
+	program.push_input(4);
+	assert_eq!(program.pending_input(), &[4]);
+	assert_eq!(program.run_until_event(), Ok(ExecutionEvent::Output(5)));
+}
+
+#[test]
+fn instruction_budget_stops_and_resumes() {
+	let mut program = load(&[104, 1, 104, 2, 104, 3, 99], Vec::new());
+	program.set_budget(Budget::instructions(2));
+	assert_eq!(program.run_until_event(), Ok(ExecutionEvent::Output(1)));
+	assert_eq!(program.run_until_event(), Ok(ExecutionEvent::Output(2)));
+	assert_eq!(
+		program.run_until_event(),
+		Ok(ExecutionEvent::BudgetExhausted)
+	);
+	assert_eq!(program.instruction_pointer(), 4);
+	assert_eq!(
+		program.run_until_event(),
+		Ok(ExecutionEvent::BudgetExhausted)
+	);
+	// A new budget is counted from when it is set.
+	program.set_budget(Budget::instructions(1));
+	assert_eq!(program.run_until_event(), Ok(ExecutionEvent::Output(3)));
+	assert_eq!(
+		program.run_until_event(),
+		Ok(ExecutionEvent::BudgetExhausted)
+	);
+	program.set_budget(Budget::default());
+	assert_eq!(program.run_until_event(), Ok(ExecutionEvent::Halted));
+	assert_eq!(program.instructions_executed(), 3);
+}
+
+#[test]
+fn run_fails_when_the_budget_runs_out() {
+	let mut program = load(LOOP, Vec::new());
+	program.set_budget(Budget::instructions(100));
+	assert_eq!(program.run(), Err(VmError::BudgetExhausted { ip: 0 }));
+}
+
+#[test]
+fn run_resumes_with_more_budget() {
+	// Counts cell 14 up to 50 in 150 instructions, then outputs it.
+	let mut program = load(
+		&[
+			1001, 14, 1, 14, 1007, 14, 50, 15, 1005, 15, 0, 4, 14, 99, 0, 0,
+		],
+		Vec::new(),
+	);
+	program.set_budget(Budget::instructions(100));
+	assert_eq!(program.run(), Err(VmError::BudgetExhausted { ip: 4 }));
+	assert_eq!(program.memory().read(14), 34);
+	program.set_budget(Budget::instructions(100));
+	assert_eq!(program.run(), Ok(vec![50]));
+	assert_eq!(program.instructions_executed(), 151);
+}
+
+#[test]
+fn time_budget_stops_an_endless_loop() {
+	let mut program = load(LOOP, Vec::new());
+	program.set_budget(Budget::time(Duration::from_millis(20)));
+	assert_eq!(
+		program.run_until_event(),
+		Ok(ExecutionEvent::BudgetExhausted)
+	);
+	assert!(program.instructions_executed() > 0);
+	assert_eq!(program.budget(), Budget::time(Duration::from_millis(20)));
+}
+
+/// Runs `instruction` on `a` and `b` in immediate mode under `policy` and
+/// returns the stored result.
+fn arithmetic(instruction: i64, a: i64, b: i64, policy: OverflowPolicy) -> Result<i64, VmError> {
+	let mut program = load(&[1100 + instruction, a, b, 7, 4, 7, 99, 0], Vec::new());
+	program.set_overflow_policy(policy);
+	program.run().map(|output| output[0])
+}
+
+#[test]
+fn checked_arithmetic_fails_on_overflow() {
+	assert_eq!(OverflowPolicy::default(), OverflowPolicy::Checked);
+	let checked = OverflowPolicy::Checked;
+	assert_eq!(arithmetic(1, i64::MAX - 1, 1, checked), Ok(i64::MAX));
+	assert_eq!(
+		arithmetic(1, i64::MAX, 1, checked),
+		Err(VmError::Overflow { ip: 0 })
+	);
+	assert_eq!(
+		arithmetic(2, i64::MIN, -1, checked),
+		Err(VmError::Overflow { ip: 0 })
+	);
+}
+
+#[test]
+fn wrapping_arithmetic_wraps() {
+	let wrapping = OverflowPolicy::Wrapping;
+	assert_eq!(arithmetic(1, i64::MAX, 1, wrapping), Ok(i64::MIN));
+	assert_eq!(arithmetic(2, i64::MAX, 2, wrapping), Ok(-2));
+}
+
+#[test]
+fn saturating_arithmetic_clamps() {
+	let saturating = OverflowPolicy::Saturating;
+	assert_eq!(arithmetic(1, i64::MAX, 1, saturating), Ok(i64::MAX));
+	assert_eq!(arithmetic(1, i64::MIN, -1, saturating), Ok(i64::MIN));
+	assert_eq!(arithmetic(2, i64::MAX, -2, saturating), Ok(i64::MIN));
+}
+
+#[test]
+fn relative_base_changes_follow_the_overflow_policy() {
+	// ARB MAX, then ARB 1.
+	let cells = [109, i64::MAX, 109, 1, 99];
+	assert_eq!(
+		load(&cells, Vec::new()).run(),
+		Err(VmError::Overflow { ip: 2 })
+	);
+	let mut program = load(&cells, Vec::new());
+	program.set_overflow_policy(OverflowPolicy::Saturating);
+	assert_eq!(program.run_until_event(), Ok(ExecutionEvent::Halted));
+	assert_eq!(*program.relative_base(), i64::MAX);
+}
+
+#[test]
+fn overwritten_instructions_are_decoded_again() {
+	// OUT 1, then write HLT over the `OUT` and jump back to it.
+	let mut program = load(&[104, 1, 1101, 0, 99, 0, 1105, 1, 0], Vec::new());
+	assert_eq!(program.run_until_event(), Ok(ExecutionEvent::Output(1)));
+	assert_eq!(program.run_until_event(), Ok(ExecutionEvent::Halted));
+	assert_eq!(program.instruction_pointer(), 0);
+}
+
+#[test]
+fn instructions_changed_through_memory_mut_are_decoded_again() {
+	// OUT 1 forever.
+	let mut program = load(&[104, 1, 1105, 1, 0], Vec::new());
+	assert_eq!(program.run_until_event(), Ok(ExecutionEvent::Output(1)));
+	program.memory_mut().write(0, 99);
+	assert_eq!(program.run_until_event(), Ok(ExecutionEvent::Halted));
+}
+
+#[test]
+fn changed_operands_of_cached_instructions_are_read() {
+	// OUT mem[9], then add 1 to it and loop.
+	let mut program = load(&[4, 9, 101, 1, 9, 9, 1105, 1, 0, 6], Vec::new());
+	let outputs: Vec<_> = (0..3).map(|_| program.run_until_event()).collect();
+	assert_eq!(
+		outputs,
+		vec![
+			Ok(ExecutionEvent::Output(6)),
+			Ok(ExecutionEvent::Output(7)),
+			Ok(ExecutionEvent::Output(8))
+		]
+	);
+}
@@ -0,0 +1,135 @@
+use icc::interpreter::{ExecutionEvent, IntCodeProgram};
+use icc::state::MachineState;
+use icc::{Memory, PAGE_SIZE};
+use std::thread;
+
+#[test]
+fn len_covers_loaded_and_written_cells() {
+	let mut memory: Memory = Memory::new(vec![1, 2, 3]);
+	assert_eq!(memory.len(), 3);
+	assert!(!memory.is_empty());
+	memory.write(9, 0);
+	assert_eq!(memory.len(), 10);
+	assert!(Memory::new(Vec::new()).is_empty());
+}
+
+#[test]
+fn new_memory_holds_i64_words() {
+	let memory = Memory::new(vec![104, 1, 99]);
+	assert_eq!(memory.read(0), 104i64);
+	let wide = Memory::from_words(vec![i128::MAX, 99]);
+	assert_eq!(wide.read(0), i128::MAX);
+}
+
+#[test]
+fn read_range_includes_untouched_cells() {
+	let mut memory: Memory = Memory::new(vec![1, 2, 3]);
+	memory.write(5, 6);
+	assert_eq!(memory.read_range(1..7), vec![2, 3, 0, 0, 6, 0]);
+}
+
+#[test]
+fn iter_nonzero_lists_cells_in_address_order() {
+	let mut memory: Memory = Memory::new(vec![0, 7, 0, 8]);
+	memory.write(5000, 9);
+	memory.write(3, 0);
+	assert_eq!(
+		memory.iter_nonzero().collect::<Vec<_>>(),
+		vec![(1, 7), (5000, 9)]
+	);
+}
+
+#[test]
+fn cells_on_both_sides_of_a_page_boundary_are_independent() {
+	let mut memory: Memory = Memory::new(Vec::new());
+	assert_eq!(memory.read(PAGE_SIZE * 3), 0);
+	memory.write(PAGE_SIZE - 1, 1);
+	memory.write(PAGE_SIZE, 2);
+	assert_eq!(
+		memory.read_range(PAGE_SIZE - 2..PAGE_SIZE + 2),
+		vec![0, 1, 2, 0]
+	);
+	assert_eq!(memory.len(), PAGE_SIZE + 1);
+}
+
+#[test]
+fn programs_larger_than_a_page_load_completely() {
+	let cells: Vec<i64> = (1..=(PAGE_SIZE as i64 * 2 + 5)).collect();
+	let memory = Memory::new(cells.clone());
+	assert_eq!(memory.len(), cells.len());
+	assert_eq!(memory.read_range(0..cells.len()), cells);
+	assert_eq!(memory.read(cells.len()), 0);
+}
+
+#[test]
+fn programs_can_use_addresses_far_past_their_code() {
+	// ARB 1000000, IN into mem[base], OUT mem[base].
+	let cells: Vec<i64> = vec![109, 1_000_000, 203, 0, 204, 0, 99];
+	let mut program = IntCodeProgram::from_memory(Memory::new(cells), vec![5]);
+	assert_eq!(program.run_until_event(), Ok(ExecutionEvent::Output(5)));
+	assert_eq!(program.memory().read(1_000_000), 5);
+	assert_eq!(program.memory().len(), 1_000_001);
+}
+
+#[test]
+#[should_panic(expected = "outside of a memory of 16 cells")]
+fn writes_past_max_size_panic() {
+	let mut memory: Memory = Memory::with_max_size(Vec::new(), 16);
+	memory.write(16, 1);
+}
+
+#[test]
+#[should_panic(expected = "doesn't fit")]
+fn programs_larger_than_max_size_are_rejected() {
+	let _: Memory = Memory::with_max_size(vec![1, 2, 3], 2);
+}
+
+#[test]
+fn clones_do_not_see_each_others_writes() {
+	let mut memory: Memory = Memory::new(vec![1; 3 * PAGE_SIZE]);
+	let mut clone = memory.clone();
+	clone.write(PAGE_SIZE, 7);
+	clone.write(10 * PAGE_SIZE, 8);
+	memory.write(0, 9);
+	assert_eq!(memory.read_range(0..2), vec![9, 1]);
+	assert_eq!(memory.read(PAGE_SIZE), 1);
+	assert_eq!(memory.read(10 * PAGE_SIZE), 0);
+	assert_eq!(memory.len(), 3 * PAGE_SIZE);
+	assert_eq!(clone.read_range(0..2), vec![1, 1]);
+	assert_eq!(clone.read(PAGE_SIZE), 7);
+	assert_eq!(clone.read(10 * PAGE_SIZE), 8);
+}
+
+#[test]
+fn forks_do_not_share_writes() {
+	// Reads a value, stores it at 9 and outputs it.
+	let cells: Vec<i64> = vec![3, 9, 4, 9, 99, 0, 0, 0, 0, 0];
+	let mut program = IntCodeProgram::from_memory(Memory::new(cells), Vec::new());
+	assert_eq!(program.run_until_event(), Ok(ExecutionEvent::NeedsInput));
+	let mut fork = program.fork();
+	fork.push_input(1);
+	program.push_input(2);
+	assert_eq!(fork.run_until_event(), Ok(ExecutionEvent::Output(1)));
+	assert_eq!(program.memory().read(9), 0);
+	fork.memory_mut().write(4, 1);
+	assert_eq!(program.run_until_event(), Ok(ExecutionEvent::Output(2)));
+	assert_eq!(program.run_until_event(), Ok(ExecutionEvent::Halted));
+	assert_eq!(fork.memory().read(9), 1);
+	assert_eq!(fork.memory().read(4), 1);
+	assert_eq!(program.memory().read(4), 99);
+}
+
+#[test]
+fn memory_and_states_can_move_to_other_threads() {
+	let memory: Memory = Memory::new(vec![1, 2, 3, 99]);
+	let mut shared = memory.clone();
+	let state: MachineState = IntCodeProgram::from_memory(memory.clone(), Vec::new()).save_state();
+	let other = thread::spawn(move || {
+		shared.write(0, 5);
+		(shared, state.memory.read(2))
+	});
+	let (shared, read) = other.join().unwrap();
+	assert_eq!(read, 3);
+	assert_eq!(shared.read(0), 5);
+	assert_eq!(memory.read(0), 1);
+}
@@ -0,0 +1,190 @@
+use icc::network::{Monitor, MonitorAction, Network, NetworkError, Packet, Scheduling, StopReason};
+use icc::Memory;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Polls once, busy-loops for about 50000 instructions, sends its address
+/// and 42 to the NAT, then polls forever.
+const SLOW_SENDER: &[i64] = &[
+	3, 100, // 0: IN address
+	3, 101, // 2: IN, polls once
+	1001, 102, 1, 102, // 4: ADD [102] 1 -> 102
+	1007, 102, 16667, 103, // 8: LESS [102] 16667 -> 103
+	1005, 103, 4, // 12: JMP [103] 4
+	104, 255, 4, 100, 104, 42, // 15: OUT 255, [100], 42
+	3, 101, 1105, 1, 21, // 21: IN and loop
+];
+
+/// Answers every packet `(x, y)` by sending `(x, y + 1)` to the NAT.
+const ECHO: &[i64] = &[
+	3, 100, // 0: IN address
+	3, 101, // 2: IN x
+	1008, 101, -1, 103, // 4: EQ [101] -1 -> 103
+	1005, 103, 2, // 8: JMP [103] 2
+	3, 102, // 11: IN y
+	1001, 102, 1, 102, // 13: ADD [102] 1 -> 102
+	104, 255, 4, 101, 4, 102, // 17: OUT 255, [101], [102]
+	1105, 1, 2, // 23: JMP 1 2
+];
+
+fn memory(cells: &[i64]) -> Memory {
+	let mut cells = cells.to_vec();
+	cells.resize(104, 0);
+	Memory::new(cells)
+}
+
+/// Records the packets sent to the NAT and how often the network went idle.
+#[derive(Clone, Default)]
+struct Recorder {
+	packets: Rc<RefCell<Vec<Packet>>>,
+	idle: Rc<RefCell<usize>>,
+}
+
+impl Monitor<i64> for Recorder {
+	fn packet(&mut self, packet: Packet) -> MonitorAction {
+		self.packets.borrow_mut().push(packet);
+		MonitorAction::Continue
+	}
+
+	fn idle(&mut self) -> MonitorAction {
+		*self.idle.borrow_mut() += 1;
+		MonitorAction::Continue
+	}
+}
+
+/// Sends the last NAT packet to machine 0 whenever the network goes idle,
+/// and stops once a packet's `y` reaches `last`.
+struct Nat {
+	packets: Rc<RefCell<Vec<Packet>>>,
+	last: i64,
+}
+
+impl Monitor<i64> for Nat {
+	fn packet(&mut self, packet: Packet) -> MonitorAction {
+		self.packets.borrow_mut().push(packet);
+		MonitorAction::Continue
+	}
+
+	fn idle(&mut self) -> MonitorAction {
+		match self.packets.borrow().last() {
+			Some(packet) if packet.y >= self.last => MonitorAction::Stop,
+			Some(packet) => MonitorAction::Send(Packet {
+				destination: 0,
+				..packet.clone()
+			}),
+			None => MonitorAction::Continue,
+		}
+	}
+}
+
+#[test]
+fn machines_cut_short_by_the_quantum_are_not_idle() {
+	let recorder = Recorder::default();
+	let mut network = Network::new(&memory(SLOW_SENDER), 2, recorder.clone());
+	assert_eq!(network.run(), Ok(StopReason::Idle));
+	let packet = |x| Packet {
+		destination: 255,
+		x,
+		y: 42,
+	};
+	assert_eq!(*recorder.packets.borrow(), vec![packet(0), packet(1)]);
+	assert_eq!(*recorder.idle.borrow(), 1);
+}
+
+#[test]
+fn the_nat_wakes_up_an_idle_network() {
+	let packets = Rc::new(RefCell::new(Vec::new()));
+	let nat = Nat {
+		packets: packets.clone(),
+		last: 4,
+	};
+	let mut network = Network::new(&memory(ECHO), 3, nat);
+	network
+		.send(Packet {
+			destination: 2,
+			x: 5,
+			y: 0,
+		})
+		.unwrap();
+	assert_eq!(network.run(), Ok(StopReason::Monitor));
+	let ys: Vec<i64> = packets.borrow().iter().map(|packet| packet.y).collect();
+	assert_eq!(ys, vec![1, 2, 3, 4]);
+	assert!(packets.borrow().iter().all(|packet| packet.x == 5));
+	assert_eq!(network.queue_len(0), 0);
+	// Routed output isn't kept by the machines.
+	assert!(network.machine(0).output().is_empty());
+}
+
+#[test]
+fn scheduling_does_not_change_what_is_delivered() {
+	for quantum in &[1, 3, 100, 10_000] {
+		let packets = Rc::new(RefCell::new(Vec::new()));
+		let nat = Nat {
+			packets: packets.clone(),
+			last: 3,
+		};
+		let mut network = Network::new(&memory(ECHO), 2, nat);
+		network.set_scheduling(Scheduling::RoundRobin { quantum: *quantum });
+		network
+			.send(Packet {
+				destination: 1,
+				x: 7,
+				y: 0,
+			})
+			.unwrap();
+		assert_eq!(network.run(), Ok(StopReason::Monitor));
+		assert_eq!(packets.borrow().len(), 3, "quantum {}", quantum);
+	}
+}
+
+#[test]
+fn an_idle_network_without_packets_stops() {
+	let recorder = Recorder::default();
+	let mut network = Network::new(&memory(ECHO), 4, recorder.clone());
+	assert_eq!(network.run(), Ok(StopReason::Idle));
+	assert!(recorder.packets.borrow().is_empty());
+	assert_eq!(*recorder.idle.borrow(), 1);
+	// Every machine read its address and is polling for a packet.
+	assert_eq!(network.machine(3).memory().read(100), 3);
+	assert_eq!(network.machine(3).instruction_pointer(), 2);
+}
+
+#[test]
+fn packets_to_unknown_machines_fail() {
+	let mut network = Network::new(&memory(ECHO), 2, ());
+	network
+		.send(Packet {
+			destination: 0,
+			x: 1,
+			y: 1,
+		})
+		.unwrap();
+	// Machine 0 answers to the NAT, which forwards nothing, so make it
+	// answer to machine 9 instead.
+	network.machine_mut(0).memory_mut().write(18, 9);
+	assert_eq!(
+		network.run(),
+		Err(NetworkError::UnknownDestination {
+			machine: 0,
+			destination: 9,
+		})
+	);
+	assert_eq!(
+		network.send(Packet {
+			destination: 2,
+			x: 0,
+			y: 0,
+		}),
+		Err(NetworkError::UnknownDestination {
+			machine: 255,
+			destination: 2,
+		})
+	);
+}
+
+#[test]
+fn halted_networks_stop() {
+	let mut network = Network::new(&memory(&[3, 100, 99]), 3, ());
+	assert_eq!(network.run(), Ok(StopReason::Halted));
+	assert_eq!(network.len(), 3);
+}
@@ -0,0 +1,104 @@
+use icc::interpreter::{Breakpoint, Budget, VmError, Watchpoint};
+use icc::pipeline::{best_phases, Pipeline, PipelineError, Topology};
+use icc::Memory;
+
+/// Day 7 example whose best chain signal is 43210, from phases 4,3,2,1,0.
+const CHAIN: &[i64] = &[
+	3, 15, 3, 16, 1002, 16, 10, 16, 1, 16, 15, 15, 4, 15, 99, 0, 0,
+];
+
+/// Day 7 example whose best feedback signal is 139629729, from phases
+/// 9,8,7,6,5.
+const RING: &[i64] = &[
+	3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27, 1001, 28, -1, 28, 1005,
+	28, 6, 99, 0, 0, 5,
+];
+
+fn memory(cells: &[i64]) -> Memory {
+	Memory::new(cells.to_vec())
+}
+
+#[test]
+fn chains_pass_the_signal_through_every_stage() {
+	let mut pipeline = Pipeline::with_phases(&memory(CHAIN), &[4, 3, 2, 1, 0], Topology::Chain);
+	assert_eq!(pipeline.len(), 5);
+	assert_eq!(pipeline.run(0), Ok(43210));
+}
+
+#[test]
+fn rings_feed_the_last_stage_back() {
+	let mut pipeline = Pipeline::with_phases(&memory(RING), &[9, 8, 7, 6, 5], Topology::Ring);
+	assert_eq!(pipeline.run(0), Ok(139629729));
+}
+
+#[test]
+fn best_phases_finds_the_highest_signal() {
+	assert_eq!(
+		best_phases(&memory(CHAIN), &[0, 1, 2, 3, 4], Topology::Chain, 0),
+		Ok((vec![4, 3, 2, 1, 0], 43210))
+	);
+	assert_eq!(
+		best_phases(&memory(RING), &[5, 6, 7, 8, 9], Topology::Ring, 0),
+		Ok((vec![9, 8, 7, 6, 5], 139629729))
+	);
+}
+
+#[test]
+fn empty_pipelines_return_the_signal() {
+	let mut pipeline: Pipeline = Pipeline::new(Vec::new(), Topology::Ring);
+	assert!(pipeline.is_empty());
+	assert_eq!(pipeline.run(7), Ok(7));
+	assert_eq!(
+		best_phases(&memory(CHAIN), &[], Topology::Chain, 7),
+		Ok((vec![], 7))
+	);
+}
+
+#[test]
+fn stages_waiting_for_input_deadlock() {
+	// Reads its phase, the signal, and then a value nobody sends.
+	let mut pipeline =
+		Pipeline::with_phases(&memory(&[3, 0, 3, 0, 3, 0, 99]), &[1, 2], Topology::Chain);
+	assert_eq!(pipeline.run(0), Err(PipelineError::Deadlock));
+}
+
+#[test]
+fn the_last_stage_must_output() {
+	let mut pipeline = Pipeline::with_phases(&memory(&[3, 0, 3, 0, 99]), &[1], Topology::Chain);
+	assert_eq!(pipeline.run(0), Err(PipelineError::NoSignal));
+}
+
+#[test]
+fn stage_errors_name_the_stage() {
+	let mut pipeline = Pipeline::with_phases(&memory(CHAIN), &[0, 1, 2], Topology::Chain);
+	pipeline.stage_mut(1).memory_mut().write(0, 42);
+	assert_eq!(
+		pipeline.run(0),
+		Err(PipelineError::Vm {
+			stage: 1,
+			error: VmError::UnknownOpcode { opcode: 42, ip: 0 },
+		})
+	);
+}
+
+#[test]
+fn exhausted_budgets_are_not_deadlocks() {
+	let mut pipeline = Pipeline::with_phases(&memory(RING), &[9, 8, 7, 6, 5], Topology::Ring);
+	pipeline.stage_mut(2).set_budget(Budget::instructions(20));
+	assert_eq!(
+		pipeline.run(0),
+		Err(PipelineError::BudgetExhausted { stage: 2 })
+	);
+	assert_eq!(
+		PipelineError::<i64>::BudgetExhausted { stage: 2 }.to_string(),
+		"Stage 2 ran out of its execution budget"
+	);
+}
+
+#[test]
+fn stages_do_not_stop_at_breakpoints() {
+	let mut pipeline = Pipeline::with_phases(&memory(CHAIN), &[4], Topology::Chain);
+	pipeline.stage_mut(0).add_breakpoint(Breakpoint::at(0));
+	pipeline.stage_mut(0).add_watchpoint(Watchpoint::write(15));
+	assert_eq!(pipeline.run(3), Ok(34));
+}
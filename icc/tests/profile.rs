@@ -0,0 +1,131 @@
+use icc::instructions::Instructions;
+use icc::interpreter::IntCodeProgram;
+use icc::profile::Profiler;
+use icc::source_map::SourceMap;
+use icc::Memory;
+use std::sync::{Arc, Mutex};
+
+/// Counts cell 20 up from 0 to 3, outputting every value.
+const COUNT: &[i64] = &[
+	1001, 20, 1, 20, 4, 20, 1007, 20, 3, 21, 1005, 21, 0, 99, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+
+fn profile(cells: &[i64]) -> Profiler {
+	let profiler = Arc::new(Mutex::new(Profiler::new()));
+	let mut program = IntCodeProgram::from_memory(Memory::new(cells.to_vec()), Vec::new());
+	program.set_tracer(profiler.clone());
+	assert_eq!(program.run(), Ok(vec![1, 2, 3]));
+	let profiler = profiler.lock().unwrap().clone();
+	profiler
+}
+
+/// Source map of `COUNT` as the assembler would build it.
+fn source_map() -> SourceMap {
+	let lines = [
+		"loop: add [counter], 1, counter",
+		"out [counter]",
+		"less [counter], 3, more",
+		"jmp [more], loop",
+		"hlt",
+	];
+	let mut map = SourceMap::new(
+		String::from("count.s"),
+		lines.iter().map(|line| line.to_string()).collect(),
+	);
+	map.add_instruction(0, Instructions::ADD, 4, 1);
+	map.add_instruction(4, Instructions::OUT, 2, 2);
+	map.add_instruction(6, Instructions::LESS, 4, 3);
+	map.add_instruction(10, Instructions::JMP, 3, 4);
+	map.add_instruction(13, Instructions::HLT, 1, 5);
+	map.add_label(String::from("loop"), 0);
+	map.add_label(String::from("counter"), 20);
+	map
+}
+
+#[test]
+fn every_execution_and_access_is_counted() {
+	let profiler = profile(COUNT);
+	assert_eq!(profiler.cycles(), 12);
+	for address in &[0, 4, 6, 10] {
+		assert_eq!(profiler.executions(*address), 3);
+	}
+	assert_eq!(profiler.executions(13), 1);
+	assert_eq!(profiler.executions(1), 0);
+
+	assert_eq!(profiler.instruction_count(Instructions::ADD), 3);
+	assert_eq!(profiler.instruction_count(Instructions::HLT), 1);
+	assert_eq!(profiler.instruction_count(Instructions::MUL), 0);
+
+	assert_eq!(profiler.reads(20), 9);
+	assert_eq!(profiler.reads(21), 3);
+	// Fetching an instruction's parameters isn't a read.
+	assert_eq!(profiler.reads(1), 0);
+	assert_eq!(profiler.writes(20), 3);
+	assert_eq!(profiler.writes(21), 3);
+}
+
+#[test]
+fn cycles_match_the_instructions_the_program_executed() {
+	let profiler = Arc::new(Mutex::new(Profiler::new()));
+	let mut program = IntCodeProgram::from_memory(Memory::new(COUNT.to_vec()), Vec::new());
+	program.set_tracer(profiler.clone());
+	program.run().unwrap();
+	// Resuming a halted program doesn't count the `HLT` again either.
+	program.run().unwrap();
+	assert_eq!(
+		profiler.lock().unwrap().cycles(),
+		program.instructions_executed()
+	);
+}
+
+#[test]
+fn hot_addresses_are_sorted_by_count_then_address() {
+	let profiler = profile(COUNT);
+	assert_eq!(profiler.hot_addresses(2), vec![(0, 3), (4, 3)]);
+	assert_eq!(profiler.hot_addresses(10).len(), 5);
+	assert_eq!(profiler.hot_addresses(10)[4], (13, 1));
+}
+
+#[test]
+fn reports_are_annotated_with_the_source() {
+	let expected = "Executed 12 instructions
+
+Hot addresses:
+       count       %  address  instr source
+           3  23.08%        0  ADD   <loop> 1: loop: add [counter], 1, counter
+           3  23.08%        4  OUT   2: out [counter]
+           3  23.08%        6  LESS  3: less [counter], 3, more
+
+Instruction mix:
+  ADD              3  23.08%
+  OUT              3  23.08%
+  JMP              3  23.08%
+  LESS             3  23.08%
+  HLT              1   7.69%
+
+Most reads:
+           9       20 <counter>
+           3       21
+
+Most writes:
+           3       20 <counter>
+           3       21
+";
+	assert_eq!(profile(COUNT).report(3, Some(&source_map())), expected);
+}
+
+#[test]
+fn reports_without_a_source_map_only_show_addresses() {
+	let report = profile(COUNT).report(1, None);
+	assert!(report.contains("        0  ADD"));
+	assert!(!report.contains("        4  OUT"));
+	assert!(!report.contains("<counter>"));
+	assert!(report.contains("  HLT              1   7.69%"));
+}
+
+#[test]
+fn empty_profiles_report_nothing() {
+	let report = Profiler::new().report(5, None);
+	assert!(report.starts_with("Executed 0 instructions\n"));
+	assert_eq!(report.lines().count(), 10);
+}
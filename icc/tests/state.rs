@@ -0,0 +1,164 @@
+use icc::interpreter::{ExecutionEvent, IntCodeProgram, OverflowPolicy};
+use icc::state::{MachineState, StateError, MAX_STATE_MEMORY};
+use icc::Memory;
+
+/// Reads two values and outputs their sum, then their product.
+const PROGRAM: &[i64] = &[
+	3, 17, 3, 18, 1, 17, 18, 19, 4, 19, 2, 17, 18, 19, 4, 19, 99, 0, 0, 0,
+];
+
+fn load(cells: &[i64], input: Vec<i64>) -> IntCodeProgram {
+	IntCodeProgram::from_memory(Memory::new(cells.to_vec()), input)
+}
+
+/// Saves `program` to text and loads it back as a new program.
+fn reload(program: &IntCodeProgram) -> IntCodeProgram {
+	let mut file = Vec::new();
+	program.save_state().write_to(&mut file).unwrap();
+	IntCodeProgram::from_state(MachineState::read_from(&file[..]).unwrap())
+}
+
+fn read(text: &str) -> Result<MachineState, StateError> {
+	MachineState::read_from(text.as_bytes())
+}
+
+/// Returns the line and message of an `Invalid` error.
+fn invalid(text: &str) -> (usize, String) {
+	match read(text) {
+		Err(StateError::Invalid { line, message }) => (line, message),
+		Err(e) => panic!("Expected an invalid line, got {}", e),
+		Ok(_) => panic!("Expected an invalid line"),
+	}
+}
+
+#[test]
+fn states_are_written_as_text() {
+	let mut program = load(PROGRAM, vec![3]);
+	assert_eq!(program.run_until_event(), Ok(ExecutionEvent::NeedsInput));
+	let mut file = Vec::new();
+	program.save_state().write_to(&mut file).unwrap();
+	let expected = "icc-state 1
+ip 2
+base 0
+executed 1
+overflow checked
+input 1 3
+output 
+memory 20 1073741824
+cells 0 3,17,3,18,1,17,18,19,4,19,2,17,18,19,4,19,99,3
+";
+	assert_eq!(String::from_utf8(file).unwrap(), expected);
+}
+
+#[test]
+fn a_program_paused_for_input_resumes_after_loading() {
+	let mut program = load(PROGRAM, vec![3]);
+	assert_eq!(program.run_until_event(), Ok(ExecutionEvent::NeedsInput));
+	let mut loaded = reload(&program);
+	assert_eq!(loaded.instruction_pointer(), 2);
+	assert_eq!(loaded.pending_input(), &[] as &[i64]);
+	loaded.push_input(4);
+	assert_eq!(loaded.run_until_event(), Ok(ExecutionEvent::Output(7)));
+
+	let mut loaded = reload(&loaded);
+	assert_eq!(loaded.output(), &[7]);
+	assert_eq!(loaded.instructions_executed(), 4);
+	assert_eq!(loaded.run(), Ok(vec![7, 12]));
+}
+
+#[test]
+fn unread_input_is_kept() {
+	let mut program = load(PROGRAM, vec![3, 4, 5]);
+	program.step().unwrap();
+	let mut loaded = reload(&program);
+	assert_eq!(loaded.pending_input(), &[4, 5]);
+	assert_eq!(loaded.run(), Ok(vec![7, 12]));
+}
+
+#[test]
+fn registers_policy_and_sparse_memory_round_trip() {
+	let mut program = load(&[109, 5000, 21101, 2, 3, 0, 99], Vec::new());
+	program.set_overflow_policy(OverflowPolicy::Wrapping);
+	assert_eq!(program.run_until_event(), Ok(ExecutionEvent::Halted));
+	let state = reload(&program).save_state();
+	assert_eq!(state.relative_base, 5000);
+	assert_eq!(state.overflow_policy, OverflowPolicy::Wrapping);
+	assert_eq!(state.memory.len(), 5001);
+	assert_eq!(state.memory.read(5000), 5);
+	assert_eq!(state.memory.read(4999), 0);
+	assert_eq!(
+		state.memory.read_range(0..7),
+		program.memory().read_range(0..7)
+	);
+}
+
+#[test]
+fn memory_length_and_size_are_kept() {
+	let mut program = IntCodeProgram::from_memory(Memory::with_max_size(vec![99], 64), Vec::new());
+	program.memory_mut().write(40, 0);
+	let loaded = reload(&program);
+	assert_eq!(loaded.memory().len(), 41);
+	assert_eq!(loaded.memory().max_size(), 64);
+}
+
+#[test]
+fn zeroed_memory_has_a_length_but_no_cells() {
+	let memory: Memory = Memory::zeroed(3000, 4096);
+	assert_eq!(memory.len(), 3000);
+	assert_eq!(memory.max_size(), 4096);
+	assert_eq!(memory.read(2999), 0);
+	assert_eq!(memory.iter_nonzero().count(), 0);
+}
+
+#[test]
+fn other_files_are_rejected() {
+	assert!(matches!(read(""), Err(StateError::NotAState)));
+	assert!(matches!(read("1,2,3,99\n"), Err(StateError::NotAState)));
+	match read("icc-state 2\nip 0\n") {
+		Err(StateError::UnsupportedVersion(version)) => assert_eq!(version, "2"),
+		_ => panic!("Expected an unsupported version"),
+	}
+}
+
+#[test]
+fn invalid_lines_are_reported() {
+	assert_eq!(
+		invalid("icc-state 1\nip -1\n"),
+		(2, String::from("Invalid instruction pointer <-1>"))
+	);
+	assert_eq!(
+		invalid("icc-state 1\nip 0\nregister 3\n"),
+		(3, String::from("Unknown entry <register>"))
+	);
+	assert_eq!(
+		invalid("icc-state 1\noverflow sometimes\n"),
+		(2, String::from("Unknown overflow policy <sometimes>"))
+	);
+	assert_eq!(
+		invalid("icc-state 1\ninput 3 1,2\n"),
+		(2, String::from("More input consumed than there is"))
+	);
+	assert_eq!(
+		invalid("icc-state 1\nmemory 10 5\n"),
+		(2, String::from("Memory length is above its maximum size"))
+	);
+	assert_eq!(
+		invalid("icc-state 1\nmemory 4 10\ncells 2 1,2,3\n"),
+		(3, String::from("Cells past the end of memory"))
+	);
+}
+
+#[test]
+fn oversized_memories_are_rejected() {
+	let text = format!(
+		"icc-state 1\nmemory {} {}\n",
+		MAX_STATE_MEMORY + 1,
+		usize::MAX
+	);
+	let (line, message) = invalid(&text);
+	assert_eq!(line, 2);
+	assert!(message.contains("above the limit"), "{}", message);
+
+	let text = format!("icc-state 1\nmemory {} {}\n", MAX_STATE_MEMORY, usize::MAX);
+	assert_eq!(read(&text).unwrap().memory.len(), MAX_STATE_MEMORY);
+}
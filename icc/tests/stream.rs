@@ -0,0 +1,151 @@
+#![cfg(feature = "async")]
+
+use futures_core::Stream;
+use icc::interpreter::{IntCodeProgram, VmError};
+use icc::profile::Profiler;
+use icc::stream::ProgramStream;
+use icc::Memory;
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::ptr;
+use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+use std::thread;
+
+/// Outputs every value it reads, forever.
+const ECHO: &[i64] = &[3, 5, 4, 5, 1105, 1, 0];
+
+/// Counts cell 20 up to 1000, then outputs it and halts.
+const SLOW: &[i64] = &[
+	1001, 20, 1, 20, 1007, 20, 1000, 21, 1005, 21, 0, 4, 20, 99, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+
+fn noop_raw_waker() -> RawWaker {
+	fn clone(_: *const ()) -> RawWaker {
+		noop_raw_waker()
+	}
+	fn noop(_: *const ()) {}
+	static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+	RawWaker::new(ptr::null(), &VTABLE)
+}
+
+fn noop_waker() -> Waker {
+	unsafe { Waker::from_raw(noop_raw_waker()) }
+}
+
+/// Input stream that is pending every other poll, starting with the first,
+/// and ends once its values run out if `ends` is set.
+struct Input {
+	values: VecDeque<i64>,
+	ready: bool,
+	ends: bool,
+}
+
+impl Input {
+	fn new(values: &[i64], ends: bool) -> Input {
+		Input {
+			values: values.iter().copied().collect(),
+			ready: true,
+			ends,
+		}
+	}
+}
+
+impl Stream for Input {
+	type Item = i64;
+
+	fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<i64>> {
+		self.ready = !self.ready;
+		if !self.ready {
+			return Poll::Pending;
+		}
+		match self.values.pop_front() {
+			Some(value) => Poll::Ready(Some(value)),
+			None if self.ends => Poll::Ready(None),
+			None => Poll::Pending,
+		}
+	}
+}
+
+fn stream(cells: &[i64], input: Input, yield_every: u64) -> ProgramStream<i64, Input> {
+	let program = IntCodeProgram::from_memory(Memory::new(cells.to_vec()), Vec::new());
+	ProgramStream::new(program, input, yield_every)
+}
+
+/// Polls `stream` until it's ready, at most `limit` times. Returns the item
+/// and how many polls were pending.
+fn poll<S: Stream + Unpin>(stream: &mut S, limit: usize) -> (Option<S::Item>, usize) {
+	let waker = noop_waker();
+	let mut cx = Context::from_waker(&waker);
+	for pending in 0..limit {
+		if let Poll::Ready(item) = Pin::new(&mut *stream).poll_next(&mut cx) {
+			return (item, pending);
+		}
+	}
+	panic!("Stream still pending after {} polls", limit);
+}
+
+#[test]
+fn outputs_are_yielded_as_input_arrives() {
+	let mut stream = stream(ECHO, Input::new(&[1, 2], true), 1000);
+	assert_eq!(poll(&mut stream, 10), (Some(Ok(1)), 1));
+	assert_eq!(poll(&mut stream, 10), (Some(Ok(2)), 1));
+	assert_eq!(
+		poll(&mut stream, 10),
+		(Some(Err(VmError::MissingInput { ip: 0 })), 1)
+	);
+	assert_eq!(poll(&mut stream, 1), (None, 0));
+}
+
+#[test]
+fn input_that_never_comes_keeps_the_stream_pending() {
+	let mut stream = stream(ECHO, Input::new(&[], false), 1000);
+	let waker = noop_waker();
+	let mut cx = Context::from_waker(&waker);
+	for _ in 0..10 {
+		assert!(Pin::new(&mut stream).poll_next(&mut cx).is_pending());
+	}
+	assert_eq!(stream.program().instruction_pointer(), 0);
+}
+
+#[test]
+fn long_computations_yield_to_the_executor() {
+	let mut stream = stream(SLOW, Input::new(&[], true), 100);
+	let (item, pending) = poll(&mut stream, 1000);
+	assert_eq!(item, Some(Ok(1000)));
+	assert_eq!(pending, 30);
+	assert_eq!(poll(&mut stream, 1), (None, 0));
+	assert_eq!(stream.program().instructions_executed(), 3001);
+}
+
+#[test]
+fn yielding_every_zero_instructions_still_makes_progress() {
+	let mut stream = stream(SLOW, Input::new(&[], true), 0);
+	let (item, pending) = poll(&mut stream, 10_000);
+	assert_eq!(item, Some(Ok(1000)));
+	assert_eq!(pending, 3000);
+}
+
+#[test]
+fn errors_end_the_stream() {
+	let mut stream = stream(&[42], Input::new(&[], true), 10);
+	assert_eq!(
+		poll(&mut stream, 1),
+		(Some(Err(VmError::UnknownOpcode { opcode: 42, ip: 0 })), 0)
+	);
+	assert_eq!(poll(&mut stream, 1), (None, 0));
+	let (program, _) = stream.into_inner();
+	assert_eq!(program.instruction_pointer(), 0);
+}
+
+/// Only compiles if `T` is `Send`.
+fn assert_send<T: Send>(_: &T) {}
+
+#[test]
+fn streams_with_handles_and_tracers_can_move_to_other_threads() {
+	let mut program = IntCodeProgram::new(Memory::new(ECHO.to_vec()), Vec::new(), |_| {}, || 7);
+	program.set_tracer(Profiler::new());
+	let mut stream = ProgramStream::new(program, Input::new(&[], false), 10);
+	assert_send(&stream);
+	let first = thread::spawn(move || poll(&mut stream, 1).0);
+	assert_eq!(first.join().unwrap(), Some(Ok(7)));
+}
@@ -0,0 +1,141 @@
+use icc::interpreter::{ExecutionEvent, IntCodeProgram, VmError};
+use icc::threaded::{spawn, Exit, ThreadError};
+use icc::Memory;
+use std::sync::mpsc::channel;
+use std::time::Duration;
+
+/// Day 7 example whose feedback signal is 139629729 with phases 9,8,7,6,5.
+const RING: &[i64] = &[
+	3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27, 1001, 28, -1, 28, 1005,
+	28, 6, 99, 0, 0, 5,
+];
+
+/// Outputs every value it reads, forever.
+const ECHO: &[i64] = &[3, 5, 4, 5, 1105, 1, 0];
+
+#[test]
+fn machines_in_a_ring_exchange_signals() {
+	let memory = Memory::new(RING.to_vec());
+	let phases = [9, 8, 7, 6, 5];
+	let channels: Vec<_> = phases.iter().map(|_| channel()).collect();
+	let (senders, receivers): (Vec<_>, Vec<_>) = channels.into_iter().unzip();
+	for (sender, phase) in senders.iter().zip(&phases) {
+		sender.send(*phase).unwrap();
+	}
+	senders[0].send(0).unwrap();
+	// The last machine's output is read here and fed back to the first.
+	let (last_output, results) = channel();
+	let mut handles = Vec::new();
+	for (stage, input) in receivers.into_iter().enumerate() {
+		let output = match senders.get(stage + 1) {
+			Some(next) => next.clone(),
+			None => last_output.clone(),
+		};
+		let memory = memory.clone();
+		handles.push(spawn(
+			move || IntCodeProgram::from_memory(memory, Vec::new()),
+			input,
+			output,
+			None,
+		));
+	}
+	drop(last_output);
+	let first = senders[0].clone();
+	drop(senders);
+	let mut signal = None;
+	for value in results {
+		signal = Some(value);
+		// The first machine may have halted already.
+		let _ = first.send(value);
+	}
+	assert_eq!(signal, Some(139629729));
+	for handle in handles {
+		assert_eq!(handle.join().unwrap(), Ok(Exit::Halted));
+	}
+}
+
+#[test]
+fn a_closed_input_stops_the_machine() {
+	let (sender, input) = channel();
+	let (output, results) = channel();
+	let handle = spawn(
+		|| IntCodeProgram::from_memory(Memory::new(ECHO.to_vec()), Vec::new()),
+		input,
+		output,
+		None,
+	);
+	sender.send(1).unwrap();
+	sender.send(2).unwrap();
+	drop(sender);
+	assert_eq!(handle.join().unwrap(), Ok(Exit::InputClosed));
+	assert_eq!(results.iter().collect::<Vec<i64>>(), vec![1, 2]);
+}
+
+#[test]
+fn a_closed_output_stops_the_machine() {
+	let (sender, input) = channel();
+	let (output, results) = channel::<i64>();
+	drop(results);
+	let handle = spawn(
+		|| IntCodeProgram::from_memory(Memory::new(ECHO.to_vec()), vec![1]),
+		input,
+		output,
+		None,
+	);
+	assert_eq!(handle.join().unwrap(), Ok(Exit::OutputClosed));
+	drop(sender);
+}
+
+#[test]
+fn waiting_too_long_for_input_fails() {
+	let (_sender, input) = channel();
+	let (output, _results) = channel::<i64>();
+	let handle = spawn(
+		|| IntCodeProgram::from_memory(Memory::new(ECHO.to_vec()), Vec::new()),
+		input,
+		output,
+		Some(Duration::from_millis(10)),
+	);
+	assert_eq!(
+		handle.join().unwrap(),
+		Err(ThreadError::InputTimeout { ip: 0 })
+	);
+}
+
+#[test]
+fn machine_errors_are_returned() {
+	let (_sender, input) = channel();
+	let (output, _results) = channel::<i64>();
+	let handle = spawn(
+		|| IntCodeProgram::from_memory(Memory::new(vec![42]), Vec::new()),
+		input,
+		output,
+		None,
+	);
+	assert_eq!(
+		handle.join().unwrap(),
+		Err(ThreadError::Vm(VmError::UnknownOpcode {
+			opcode: 42,
+			ip: 0
+		}))
+	);
+}
+
+#[test]
+fn saved_states_resume_on_another_thread() {
+	let mut program = IntCodeProgram::from_memory(Memory::new(ECHO.to_vec()), vec![7]);
+	assert_eq!(program.run_until_event(), Ok(ExecutionEvent::Output(7)));
+	let state = program.save_state();
+	let (sender, input) = channel();
+	let (output, results) = channel();
+	let handle = spawn(
+		move || IntCodeProgram::from_state(state),
+		input,
+		output,
+		None,
+	);
+	sender.send(8).unwrap();
+	drop(sender);
+	assert_eq!(handle.join().unwrap(), Ok(Exit::InputClosed));
+	assert_eq!(results.iter().collect::<Vec<i64>>(), vec![8]);
+}
@@ -0,0 +1,121 @@
+use icc::interpreter::{ExecutionEvent, IntCodeProgram, StepInfo};
+use icc::trace::{JsonLinesTracer, Tracer};
+use icc::Memory;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+/// Reads a value into a relative cell, outputs it and exercises a write and
+/// a jump that isn't taken.
+const PROGRAM: &[i64] = &[
+	109, 10, 203, 4, 204, 4, 1101, 2, 3, 16, 1005, 17, 0, 99, 0, 0, 0, 0,
+];
+
+fn load(cells: &[i64], input: Vec<i64>) -> IntCodeProgram {
+	IntCodeProgram::from_memory(Memory::new(cells.to_vec()), input)
+}
+
+/// Runs `program` to the end and returns its JSON Lines trace.
+fn trace(mut program: IntCodeProgram) -> String {
+	let tracer = Arc::new(Mutex::new(JsonLinesTracer::new(Vec::new())));
+	program.set_tracer(tracer.clone());
+	program.run().unwrap();
+	tracer.lock().unwrap().finish().unwrap();
+	drop(program);
+	let tracer = Arc::try_unwrap(tracer).ok().unwrap().into_inner().unwrap();
+	String::from_utf8(tracer.into_inner()).unwrap()
+}
+
+#[test]
+fn every_instruction_is_a_json_line() {
+	let expected = [
+		r#"{"step":0,"ip":0,"opcode":109,"instruction":"ARB","modes":["immediate"],"operands":[10],"values":[10],"rb":10}"#,
+		r#"{"step":1,"ip":2,"opcode":203,"instruction":"IN","modes":["relative_immediate"],"operands":[4],"values":[14],"write":{"address":14,"value":42},"input":42}"#,
+		r#"{"step":2,"ip":4,"opcode":204,"instruction":"OUT","modes":["relative"],"operands":[4],"values":[42],"output":42}"#,
+		r#"{"step":3,"ip":6,"opcode":1101,"instruction":"ADD","modes":["immediate","immediate","immediate"],"operands":[2,3,16],"values":[2,3,16],"write":{"address":16,"value":5}}"#,
+		r#"{"step":4,"ip":10,"opcode":1005,"instruction":"JMP","modes":["position","immediate"],"operands":[17,0],"values":[0,0]}"#,
+		r#"{"step":5,"ip":13,"opcode":99,"instruction":"HLT","modes":[],"operands":[],"values":[]}"#,
+	];
+	let trace = trace(load(PROGRAM, vec![42]));
+	assert_eq!(trace.lines().collect::<Vec<_>>(), expected);
+}
+
+#[test]
+fn input_that_is_not_there_yet_is_not_traced() {
+	let mut program = load(PROGRAM, Vec::new());
+	let tracer = Arc::new(Mutex::new(Steps::default()));
+	program.set_tracer(tracer.clone());
+	program.run_until_event().unwrap();
+	program.run_until_event().unwrap();
+	assert_eq!(tracer.lock().unwrap().0, vec![(0, 0)]);
+	program.push_input(42);
+	program.run_until_event().unwrap();
+	assert_eq!(tracer.lock().unwrap().0, vec![(0, 0), (1, 2), (2, 4)]);
+}
+
+/// Accepts a fixed number of bytes, then fails every write.
+struct ShortWriter(usize);
+
+impl Write for ShortWriter {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		if self.0 == 0 {
+			return Err(io::Error::new(io::ErrorKind::WriteZero, "full"));
+		}
+		let written = buf.len().min(self.0);
+		self.0 -= written;
+		Ok(written)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+#[test]
+fn write_errors_stop_tracing_and_are_returned_by_finish() {
+	let mut program = load(PROGRAM, vec![42]);
+	let tracer = Arc::new(Mutex::new(JsonLinesTracer::new(ShortWriter(10))));
+	program.set_tracer(tracer.clone());
+	// The program itself isn't affected by the tracer failing.
+	assert_eq!(program.run(), Ok(vec![42]));
+	let error = tracer.lock().unwrap().finish().unwrap_err();
+	assert_eq!(error.kind(), io::ErrorKind::WriteZero);
+	assert!(tracer.lock().unwrap().finish().is_ok());
+}
+
+/// Records the step numbers and addresses it sees.
+#[derive(Default)]
+struct Steps(Vec<(u64, usize)>);
+
+impl Tracer<i64> for Steps {
+	fn trace(&mut self, step_number: u64, step: &StepInfo, _: &i64) {
+		self.0.push((step_number, step.address));
+	}
+}
+
+#[test]
+fn a_list_of_tracers_sees_every_step() {
+	let first = Arc::new(Mutex::new(Steps::default()));
+	let second = Arc::new(Mutex::new(Steps::default()));
+	let tracers: Vec<Box<dyn Tracer<i64> + Send>> =
+		vec![Box::new(first.clone()), Box::new(second.clone())];
+	let mut program = load(PROGRAM, vec![42]);
+	program.set_tracer(tracers);
+	program.run_until_event().unwrap();
+	let steps = vec![(0, 0), (1, 2), (2, 4)];
+	assert_eq!(first.lock().unwrap().0, steps);
+	assert_eq!(second.lock().unwrap().0, steps);
+	assert!(program.take_tracer().unwrap().finish().is_ok());
+}
+
+#[test]
+fn a_halted_program_is_traced_once() {
+	let mut program = load(PROGRAM, vec![42]);
+	let tracer = Arc::new(Mutex::new(Steps::default()));
+	program.set_tracer(tracer.clone());
+	while program.run_until_event().unwrap() != ExecutionEvent::Halted {}
+	assert_eq!(program.run_until_event(), Ok(ExecutionEvent::Halted));
+	assert_eq!(program.step().unwrap().event, Some(ExecutionEvent::Halted));
+	assert_eq!(tracer.lock().unwrap().0.len(), 6);
+	assert_eq!(tracer.lock().unwrap().0[5], (5, 13));
+	assert_eq!(program.instructions_executed(), 5);
+}
@@ -0,0 +1,57 @@
+use icc::interpreter::{IntCodeProgram, VmError};
+use icc::word::Word;
+use icc::Memory;
+
+/// Multiplies two immediates and outputs the product.
+fn product<W: Word>(a: W, b: W) -> Result<Vec<W>, VmError<W>> {
+	let cells = vec![
+		W::from_i64(1102),
+		a,
+		b,
+		W::from_i64(7),
+		W::from_i64(4),
+		W::from_i64(7),
+		W::from_i64(99),
+		W::zero(),
+	];
+	IntCodeProgram::from_memory(Memory::from_words(cells), Vec::new()).run()
+}
+
+#[test]
+fn i128_words_go_past_i64() {
+	let big = i64::MAX as i128;
+	assert_eq!(product(big, 4), Ok(vec![big * 4]));
+	assert_eq!(product(i64::MAX, 4), Err(VmError::Overflow { ip: 0 }));
+}
+
+#[test]
+fn addresses_must_fit_in_i64() {
+	let address = i64::MAX as i128 + 1;
+	let cells = vec![4, address, 99];
+	let mut program = IntCodeProgram::from_memory(Memory::from_words(cells), Vec::new());
+	assert_eq!(
+		program.run(),
+		Err(VmError::AddressOutOfRange {
+			addr: address,
+			ip: 0
+		})
+	);
+}
+
+#[test]
+fn words_convert_through_i64() {
+	assert_eq!(i128::from_i64(-5), -5);
+	assert_eq!((-5i128).to_i64(), Some(-5));
+	assert_eq!(i128::MAX.to_i64(), None);
+	assert!(i128::zero().is_zero());
+}
+
+#[cfg(feature = "bigint")]
+#[test]
+fn bigint_words_never_overflow() {
+	use icc::word::BigInt;
+
+	let big = BigInt::from(u64::MAX);
+	let expected = &big * &big;
+	assert_eq!(product(big.clone(), big), Ok(vec![expected]));
+}
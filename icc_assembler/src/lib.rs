@@ -1,18 +1,12 @@
-use icc::instructions::{Instructions, ParameterModes};
+use icc::instructions::{encode_opcode, Instructions, ParameterModes};
+use icc::source_map::SourceMap;
+use regex::Regex;
 use std::collections::hash_map::HashMap;
-
-pub fn get_mode_number_from_mode(mode: ParameterModes) -> (char, bool) {
-	return match mode {
-		ParameterModes::Position => ('0', false),
-		ParameterModes::Immediate => ('1', true),
-		ParameterModes::Relative => ('2', true),
-		_ => panic!("Unknown mode"),
-	};
-}
+use std::iter;
 
 pub const DEFAULT_MODE: ParameterModes = ParameterModes::Relative;
 
-pub fn parse_argument(arg: &String) -> Result<(ParameterModes, String, bool), String> {
+pub fn parse_argument(arg: &str) -> Result<(ParameterModes, String, bool), String> {
 	let mut char_iter = arg.chars();
 	let first_char = match char_iter.next() {
 		Some(c) => c,
@@ -31,8 +25,7 @@ pub fn parse_argument(arg: &String) -> Result<(ParameterModes, String, bool), St
 		}
 	}
 
-	let second_char;
-	second_char = char_iter.next();
+	let second_char = char_iter.next();
 
 	let mut output = String::new();
 	if mode == DEFAULT_MODE {
@@ -50,8 +43,40 @@ pub fn parse_argument(arg: &String) -> Result<(ParameterModes, String, bool), St
 	Ok((mode, output, is_tag))
 }
 
-pub fn parse_instruction<'a>(instr: &'a String) -> (String, Option<String>) {
-	let possible_tag: Vec<&str> = instr.split(":").collect();
+/// Splits assembly source into lines of comma separated arguments, joining
+/// every label with the instruction that follows it. The joined line takes the
+/// instruction's place, so each line keeps its position in the source.
+pub fn preprocess(source: &str) -> Vec<Vec<String>> {
+	let re = Regex::new(r" *:( *\n*\r*)*").unwrap();
+	let mut joined = String::with_capacity(source.len());
+	let mut last = 0;
+	for label_end in re.find_iter(source) {
+		let line_start = source[..label_end.start()]
+			.rfind('\n')
+			.map_or(0, |i| i + 1)
+			.max(last);
+		joined.push_str(&source[last..line_start]);
+		joined.extend(iter::repeat_n(
+			'\n',
+			label_end.as_str().matches('\n').count(),
+		));
+		joined.push_str(&source[line_start..label_end.start()]);
+		joined.push(':');
+		last = label_end.end();
+	}
+	joined.push_str(&source[last..]);
+	joined
+		.split('\n')
+		.map(|arg| {
+			arg.split(',')
+				.map(|arg| arg.trim_start().trim_end().replace('\r', ""))
+				.collect()
+		})
+		.collect()
+}
+
+pub fn parse_instruction(instr: &str) -> (String, Option<String>) {
+	let possible_tag: Vec<&str> = instr.split(':').collect();
 	let mut tag: Option<String> = None;
 	let instr = if possible_tag.len() > 1 {
 		tag = Some(String::from(possible_tag[0]));
@@ -85,6 +110,7 @@ pub enum CompileErrorType {
 	ArgumentParse(String),
 	UndefinedTag(String),
 	UnusedTag(String),
+	WriteToImmediate(usize),
 }
 
 pub struct CompileError<'a> {
@@ -94,11 +120,11 @@ pub struct CompileError<'a> {
 }
 
 impl CompileError<'_> {
-	pub fn new<'a>(error: CompileErrorType, file: &'a str, pos: CodePosition) -> CompileError {
+	pub fn new(error: CompileErrorType, file: &str, pos: CodePosition) -> CompileError<'_> {
 		CompileError {
 			error_type: error,
-			file: file,
-			pos: pos,
+			file,
+			pos,
 		}
 	}
 }
@@ -136,6 +162,11 @@ impl fmt::Display for CompileError<'_> {
 			CompileErrorType::UnusedTag(tag) => {
 				write!(f, "{}Tag <{}> is never used.", file_name, tag)
 			}
+			CompileErrorType::WriteToImmediate(arg) => write!(
+				f,
+				"{}Argument {} is written to and can't be immediate",
+				file_name, arg
+			),
 		}
 	}
 }
@@ -147,32 +178,45 @@ pub struct Assembler {
 	current_line: usize,
 	current_address: usize,
 	filename: String,
+	source_map: SourceMap,
 }
 
 impl Assembler {
 	pub fn new(input: Vec<Vec<String>>, file: String) -> Assembler {
 		Assembler {
-			input: input,
+			input,
 			tag_definitions: HashMap::new(),
 			tag_uses: HashMap::new(),
 			current_line: 0,
 			current_address: 0,
+			source_map: SourceMap::new(file.clone(), Vec::new()),
 			filename: file,
 		}
 	}
 
-	pub fn compile(&mut self, should_format: bool) -> Result<Vec<String>, Vec<CompileError>> {
+	/// Creates an assembler for the contents of a source file. Its source map
+	/// keeps the text of every line.
+	pub fn from_source(source: &str, file: String) -> Assembler {
+		let mut assembler = Assembler::new(preprocess(source), file);
+		let lines = source.lines().map(String::from).collect();
+		assembler.source_map = SourceMap::new(assembler.filename.clone(), lines);
+		assembler
+	}
+
+	/// Maps the addresses of the compiled program to source lines and labels.
+	/// Filled in by `compile`.
+	pub fn source_map(&self) -> &SourceMap {
+		&self.source_map
+	}
+
+	pub fn compile(&mut self, should_format: bool) -> Result<Vec<String>, Vec<CompileError<'_>>> {
 		let mut output: Vec<String> = Vec::new();
-		let opcodes = Instructions::new();
 		let mut errors_found: Vec<CompileError> = Vec::new();
 		for instruction in self.input.iter_mut() {
 			self.current_line += 1;
-			let instr: Vec<String> = instruction[0]
-				.split(" ")
-				.map(|arg| String::from(arg))
-				.collect();
+			let instr: Vec<String> = instruction[0].split(" ").map(String::from).collect();
 			if instr.len() > 1 {
-				instruction[0] = String::from(instr[1].clone());
+				instruction[0] = instr[1].clone();
 			}
 			if instr[0].is_empty() || instr[0].find("\r").unwrap_or(1) == 0 {
 				continue;
@@ -200,13 +244,13 @@ impl Assembler {
 					));
 					continue;
 				}
-				self.tag_definitions.insert(String::from(t), pos);
+				self.tag_definitions.insert(t, pos);
 			}
 			let instr = match Instructions::get_instruction_from_name(&instr[..]) {
 				Some(i) => i,
 				None => {
 					errors_found.push(CompileError::new(
-						CompileErrorType::UnknownInstruction(String::from(instr)),
+						CompileErrorType::UnknownInstruction(instr),
 						&self.filename[..],
 						CodePosition {
 							line: self.current_line,
@@ -217,8 +261,8 @@ impl Assembler {
 					continue;
 				}
 			};
-			instruction.retain(|x| x != "");
-			let arg_count = opcodes[&instr].arguments_count;
+			instruction.retain(|x| !x.is_empty());
+			let arg_count = instr.data().arguments_count;
 			if instruction.len() != arg_count as usize && arg_count != 0 {
 				errors_found.push(CompileError::new(
 					CompileErrorType::WrongArgumentsCount(arg_count, instruction.len()),
@@ -230,12 +274,12 @@ impl Assembler {
 					},
 				));
 			}
-			let opc = instr.get_instruction_opc();
+			let instruction_address = self.current_address;
+			let write_argument = instr.data().write_argument;
 			let mut modes = Vec::new();
 			let mut args: Vec<String> = Vec::new();
-			for i in 0..arg_count as usize {
+			for (i, arg) in instruction.iter().enumerate().take(arg_count as usize) {
 				self.current_address += 1;
-				let arg = &instruction[i];
 				let (mode, arg_string, is_tag) = match parse_argument(arg) {
 					Ok(o) => o,
 					Err(e) => {
@@ -251,6 +295,25 @@ impl Assembler {
 						continue;
 					}
 				};
+				// The written argument holds an address: `#` writes to it directly
+				// and the default mode writes relative to the relative base.
+				let mode = match (mode, write_argument == Some(i as u8)) {
+					(ParameterModes::Position, true) => ParameterModes::Immediate,
+					(ParameterModes::Relative, true) => ParameterModes::RelativeImmediate,
+					(_, true) => {
+						errors_found.push(CompileError::new(
+							CompileErrorType::WriteToImmediate(i + 1),
+							&self.filename[..],
+							CodePosition {
+								line: self.current_line,
+								column: i + 1,
+								address: self.current_address,
+							},
+						));
+						ParameterModes::Immediate
+					}
+					(mode, false) => mode,
+				};
 				modes.push(mode);
 				args.push(arg_string.clone());
 				if is_tag {
@@ -265,33 +328,38 @@ impl Assembler {
 					});
 				}
 			}
-			modes.reverse();
 			let mut opcode_output;
 			if should_format {
 				opcode_output = String::from("\n")
 			} else {
 				opcode_output = String::new();
 			}
-
-			let mut found_1 = false;
-			for mode in modes {
-				let (ch, found) = get_mode_number_from_mode(mode);
-				if found {
-					found_1 = found;
-				}
-				if found_1 || ch != '0' {
-					opcode_output.push(ch);
+			// A wrong argument count was already reported above.
+			if modes.len() == arg_count as usize {
+				match encode_opcode(instr, &modes) {
+					Ok(opc) => opcode_output.push_str(opc.to_string().as_str()),
+					Err(e) => errors_found.push(CompileError::new(
+						CompileErrorType::ArgumentParse(e.to_string()),
+						&self.filename[..],
+						CodePosition {
+							line: self.current_line,
+							column: 0,
+							address: self.current_address,
+						},
+					)),
 				}
 			}
-			if opc < 10 && found_1 {
-				opcode_output.push('0');
-			}
-			opcode_output.push_str(opc.to_string().as_str());
 			output.push(opcode_output);
 			for arg in args {
 				output.push(arg);
 			}
 			self.current_address += 1;
+			self.source_map.add_instruction(
+				instruction_address,
+				instr,
+				self.current_address - instruction_address,
+				self.current_line,
+			);
 		}
 		self.tag_definitions.insert(
 			String::from("data"),
@@ -318,6 +386,7 @@ impl Assembler {
 			}
 		}
 		for (tag, pos) in &self.tag_definitions {
+			self.source_map.add_label(tag.clone(), pos.address);
 			if !self.tag_uses.contains_key(tag) {
 				errors_found.push(CompileError {
 					error_type: CompileErrorType::UnusedTag(tag.clone()),
@@ -326,7 +395,7 @@ impl Assembler {
 				});
 			}
 		}
-		if errors_found.len() > 0 {
+		if !errors_found.is_empty() {
 			Err(errors_found)
 		} else {
 			Ok(output)
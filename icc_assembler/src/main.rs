@@ -1,12 +1,11 @@
 extern crate clap;
-extern crate regex;
+extern crate icc_assembler;
 
 use clap::{App, Arg};
-use regex::Regex;
 use std::fs;
 use std::io::Write;
 
-mod lib;
+use icc_assembler::Assembler;
 
 fn main() {
     let matches = App::new("icc_assembler")
@@ -47,18 +46,7 @@ fn main() {
         }
     };
 
-    let re = Regex::new(r" *:( *\n*\r*)*").unwrap();
-    let input = re.replace_all(input.as_str(), ":");
-    let input: Vec<Vec<String>> = input
-        .split("\n")
-        .map(|arg| {
-            arg.split(",")
-                .map(|arg| arg.trim_start().trim_end().replace("\r", ""))
-                .collect()
-        })
-        .collect();
-
-    let mut assembler = lib::Assembler::new(input, filename.to_string());
+    let mut assembler = Assembler::from_source(&input, filename.to_string());
 
     match assembler.compile(should_format) {
         Err(errors_found) => {
@@ -93,7 +81,7 @@ fn main() {
                     return;
                 }
             };
-            file.write(output.join(",").trim_start().as_bytes())
+            file.write_all(output.join(",").trim_start().as_bytes())
                 .unwrap();
             println!("Succesfully compiled {} to {}", filename, output_name);
         }
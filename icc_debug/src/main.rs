@@ -0,0 +1,461 @@
+extern crate clap;
+extern crate icc;
+extern crate icc_assembler;
+
+use clap::{App, Arg};
+use icc::instructions::decode;
+use icc::interpreter::{Access, Breakpoint, ExecutionEvent, IntCodeProgram, VmError, Watchpoint};
+use icc::source_map::SourceMap;
+use icc::Memory;
+use icc_assembler::Assembler;
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "Commands:
+	step [n]              execute n instructions (s)
+	next                  run until the instruction after this one (n)
+	continue              run until a breakpoint, watchpoint or halt (c)
+	break [addr|label]    set a breakpoint, or list them without arguments (b)
+	watch <addr> [r|w|rw] stop when a cell is read and/or written, default w
+	delete <id>           remove a breakpoint or watchpoint
+	print mem[a..b]       print memory cells, or a single one with mem[a] (p)
+	set mem[x]=v          write a memory cell, ip=v and rb=v set registers
+	regs                  show the instruction pointer and relative base
+	disasm [n]            disassemble n instructions around the ip (d)
+	input <v>...          queue input values
+	quit                  exit (q)
+An empty line repeats the last command.";
+
+struct Debugger {
+	program: IntCodeProgram,
+	source_map: Option<SourceMap>,
+}
+
+impl Debugger {
+	/// Parses an address given as a number or a label name.
+	fn address(&self, arg: &str) -> Result<usize, String> {
+		if let Ok(address) = arg.parse() {
+			return Ok(address);
+		}
+		self.source_map
+			.as_ref()
+			.and_then(|map| map.label_address(arg))
+			.ok_or(format!("<{}> is neither an address nor a label", arg))
+	}
+
+	/// Parses `mem[a]` or `mem[a..b]` into an address range.
+	fn memory_range(&self, arg: &str) -> Result<(usize, usize), String> {
+		let inner = arg
+			.strip_prefix("mem[")
+			.and_then(|rest| rest.strip_suffix(']'))
+			.ok_or(format!("Expected mem[a] or mem[a..b], found <{}>", arg))?;
+		match inner.find("..") {
+			Some(i) => Ok((
+				self.address(inner[..i].trim())?,
+				self.address(inner[i + 2..].trim())?,
+			)),
+			None => {
+				let address = self.address(inner.trim())?;
+				let end = address
+					.checked_add(1)
+					.ok_or(format!("Address {} is out of range", address))?;
+				Ok((address, end))
+			}
+		}
+	}
+
+	/// Describes an address with its label and source position when known.
+	fn describe(&self, address: usize) -> String {
+		let mut description = address.to_string();
+		if let Some(map) = &self.source_map {
+			if let Some(label) = map.label_at(address) {
+				description.push_str(&format!(" <{}>", label));
+			}
+			if let Some(line) = map.line(address) {
+				description.push_str(&format!(" {}:{}", map.file(), line));
+			}
+		}
+		description
+	}
+
+	/// Formats the instruction at `address` and returns its size.
+	fn disassemble(&self, address: usize) -> (String, usize) {
+		let (text, size) = match decode(self.program.memory(), address) {
+			Ok(instruction) => (instruction.to_string(), instruction.size()),
+			Err(_) => (format!("{} (data)", self.program.memory().read(address)), 1),
+		};
+		match self
+			.source_map
+			.as_ref()
+			.and_then(|map| map.source_at(address))
+		{
+			Some(source) => (format!("{:<24}; {}", text, source.trim()), size),
+			None => (text, size),
+		}
+	}
+
+	fn show_location(&self) {
+		let ip = self.program.instruction_pointer();
+		println!("=> {}", self.describe(ip));
+		println!("   {}", self.disassemble(ip).0);
+	}
+
+	fn disassemble_around(&self, count: usize) {
+		let ip = self.program.instruction_pointer();
+		// Instructions can only be walked backwards with the source map.
+		let mut address = match &self.source_map {
+			Some(map) => {
+				let before: Vec<usize> = map
+					.instructions()
+					.map(|(address, _)| address)
+					.filter(|address| *address < ip)
+					.collect();
+				let first = before.len().saturating_sub(count / 2);
+				before.get(first).copied().unwrap_or(ip)
+			}
+			None => ip,
+		};
+		for _ in 0..count {
+			let (text, size) = self.disassemble(address);
+			let marker = if address == ip { "=>" } else { "  " };
+			let label = self
+				.source_map
+				.as_ref()
+				.and_then(|map| map.label_at(address))
+				.map(|label| format!("<{}>", label))
+				.unwrap_or_default();
+			println!("{} {:>6} {:<12} {}", marker, address, label, text);
+			address += size;
+		}
+	}
+
+	/// Prints an execution event. Returns `true` if execution should stop.
+	fn report(&self, event: Result<Option<ExecutionEvent>, VmError>) -> bool {
+		match event {
+			Ok(None) => false,
+			Ok(Some(ExecutionEvent::Output(value))) => {
+				println!("Output: {}", value);
+				false
+			}
+			Ok(Some(ExecutionEvent::NeedsInput)) => {
+				println!("Program needs input, queue it with `input <value>`");
+				true
+			}
+			Ok(Some(ExecutionEvent::Halted)) => {
+				println!("Program halted");
+				true
+			}
+			Ok(Some(ExecutionEvent::BudgetExhausted)) => true,
+			Ok(Some(ExecutionEvent::Breakpoint { id, ip })) => {
+				println!("Breakpoint {} at {}", id, self.describe(ip));
+				true
+			}
+			Ok(Some(ExecutionEvent::Watchpoint {
+				id,
+				ip,
+				address,
+				access,
+			})) => {
+				let access = match access {
+					Access::Read => "read",
+					Access::Write => "written",
+				};
+				println!(
+					"Watchpoint {}: {} {} by {}, value {}",
+					id,
+					address,
+					access,
+					self.describe(ip),
+					self.program.memory().read(address)
+				);
+				true
+			}
+			Err(e) => {
+				println!("Program failed: {}", e);
+				true
+			}
+		}
+	}
+
+	fn step(&mut self, count: usize) {
+		for _ in 0..count {
+			let event = self.program.step().map(|step| step.event);
+			if self.report(event) {
+				break;
+			}
+		}
+		self.show_location();
+	}
+
+	/// Runs until something stops the program, or until `until` is reached.
+	fn resume(&mut self, until: Option<usize>) {
+		let ip = self.program.instruction_pointer();
+		let executed = self.program.instructions_executed();
+		let temporary = until.map(|address| self.program.add_breakpoint(Breakpoint::at(address)));
+		loop {
+			let event = self.program.run_until_event();
+			let stopped = match (event, temporary) {
+				// A breakpoint on the current instruction stops before anything
+				// ran. Resuming again steps off it.
+				(Ok(ExecutionEvent::Breakpoint { ip: at, .. }), _)
+					if at == ip && self.program.instructions_executed() == executed =>
+				{
+					false
+				}
+				(Ok(ExecutionEvent::Breakpoint { id, .. }), Some(temporary)) if id == temporary => {
+					true
+				}
+				(event, _) => self.report(event.map(Some)),
+			};
+			if stopped {
+				break;
+			}
+		}
+		if let Some(temporary) = temporary {
+			self.program.remove_breakpoint(temporary);
+		}
+		self.show_location();
+	}
+
+	fn list_breakpoints(&self) {
+		for (id, breakpoint) in self.program.breakpoints() {
+			if let Some(address) = breakpoint.address {
+				println!("{}: break at {}", id, self.describe(address));
+			}
+		}
+		for (id, watchpoint) in self.program.watchpoints() {
+			let kind = match (watchpoint.on_read, watchpoint.on_write) {
+				(true, true) => "rw",
+				(true, false) => "r",
+				_ => "w",
+			};
+			println!("{}: watch {} {}", id, watchpoint.address, kind);
+		}
+	}
+
+	fn set(&mut self, assignment: &str) -> Result<(), String> {
+		let (target, value) = match assignment.find('=') {
+			Some(i) => (assignment[..i].trim(), assignment[i + 1..].trim()),
+			None => return Err(String::from("Expected <target>=<value>")),
+		};
+		let value: i64 = value
+			.parse()
+			.map_err(|e| format!("Invalid value <{}>: {}", value, e))?;
+		match target {
+			"ip" => {
+				let address = usize::try_from(value)
+					.map_err(|_| format!("Invalid instruction pointer <{}>", value))?;
+				self.program.set_instruction_pointer(address);
+			}
+			"rb" => self.program.set_relative_base(value),
+			_ => {
+				let (address, _) = self.memory_range(target)?;
+				let max_size = self.program.memory().max_size();
+				if address >= max_size {
+					return Err(format!(
+						"Address {} is outside of a memory of {} cells",
+						address, max_size
+					));
+				}
+				self.program.memory_mut().write(address, value);
+			}
+		}
+		Ok(())
+	}
+
+	/// Runs one command. Returns `false` once the debugger should exit.
+	fn execute(&mut self, line: &str) -> Result<bool, String> {
+		let mut words = line.split_whitespace();
+		let command = match words.next() {
+			Some(command) => command,
+			None => return Ok(true),
+		};
+		let args: Vec<&str> = words.collect();
+		let count = |default: usize| -> Result<usize, String> {
+			match args.first() {
+				Some(arg) => arg.parse().map_err(|e| format!("Invalid count: {}", e)),
+				None => Ok(default),
+			}
+		};
+		match command {
+			"step" | "s" => self.step(count(1)?),
+			"next" | "n" => {
+				let ip = self.program.instruction_pointer();
+				let (_, size) = self.disassemble(ip);
+				self.resume(Some(ip + size));
+			}
+			"continue" | "c" => self.resume(None),
+			"break" | "b" => match args.first() {
+				Some(arg) => {
+					let address = self.address(arg)?;
+					let id = self.program.add_breakpoint(Breakpoint::at(address));
+					println!("Breakpoint {} set at {}", id, self.describe(address));
+				}
+				None => self.list_breakpoints(),
+			},
+			"watch" | "w" => {
+				let address = self.address(args.first().ok_or("Expected an address")?)?;
+				let watchpoint = match args.get(1).copied().unwrap_or("w") {
+					"r" => Watchpoint::read(address),
+					"w" => Watchpoint::write(address),
+					"rw" => Watchpoint::access(address),
+					kind => return Err(format!("Unknown watch kind <{}>", kind)),
+				};
+				let id = self.program.add_watchpoint(watchpoint);
+				println!("Watchpoint {} set on {}", id, address);
+			}
+			"delete" => {
+				let arg = args.first().ok_or("Expected a breakpoint id")?;
+				let id = self
+					.program
+					.breakpoints()
+					.map(|(id, _)| id)
+					.chain(self.program.watchpoints().map(|(id, _)| id))
+					.find(|id| id.to_string() == *arg)
+					.ok_or(format!("No breakpoint {}", arg))?;
+				self.program.remove_breakpoint(id);
+			}
+			"print" | "p" => {
+				let (start, end) = self.memory_range(&args.concat())?;
+				for address in start..end {
+					println!("{:>6}: {}", address, self.program.memory().read(address));
+				}
+			}
+			"set" => self.set(&args.concat())?,
+			"regs" => {
+				println!("ip = {}", self.describe(self.program.instruction_pointer()));
+				println!("rb = {}", self.program.relative_base());
+				println!("executed = {}", self.program.instructions_executed());
+				println!("input = {:?}", self.program.pending_input());
+			}
+			"disasm" | "d" => self.disassemble_around(count(8)?),
+			"input" | "i" => {
+				for arg in args {
+					let value = arg
+						.parse()
+						.map_err(|e| format!("Invalid input <{}>: {}", arg, e))?;
+					self.program.push_input(value);
+				}
+			}
+			"help" | "h" => println!("{}", HELP),
+			"quit" | "q" => return Ok(false),
+			_ => return Err(format!("Unknown command <{}>, try `help`", command)),
+		}
+		Ok(true)
+	}
+}
+
+fn main() {
+	let matches = App::new("icc-debug")
+		.version("0.1.0")
+		.about("Steps through an icc program interactively")
+		.arg(
+			Arg::with_name("Source")
+				.short("s")
+				.long("source")
+				.value_name("SOURCE")
+				.help("Assembly the program was compiled from, to show source lines and labels")
+				.takes_value(true),
+		)
+		.arg(
+			Arg::with_name("Program")
+				.help("Sets the program to debug.")
+				.required(true)
+				.index(1),
+		)
+		.arg(
+			Arg::with_name("Input")
+				.help("Values queued as program input.")
+				.multiple(true)
+				.index(2),
+		)
+		.get_matches();
+
+	let filename = matches.value_of("Program").unwrap();
+	let program = match fs::read_to_string(filename) {
+		Ok(o) => o,
+		Err(e) => {
+			println!("Problem reading file {}: {}", filename, e);
+			return;
+		}
+	};
+	let memory: Result<Vec<i64>, _> = program
+		.replace(" ", "")
+		.replace(",\n", ",")
+		.replace("\n", ",")
+		.split(",")
+		.map(|cell| cell.parse())
+		.collect();
+	let memory = match memory {
+		Ok(memory) => memory,
+		Err(e) => {
+			println!("Problem parsing program {}: {}", filename, e);
+			return;
+		}
+	};
+	let mut input = Vec::new();
+	for value in matches.values_of("Input").into_iter().flatten() {
+		match value.parse() {
+			Ok(value) => input.push(value),
+			Err(e) => {
+				println!("Invalid input <{}>: {}", value, e);
+				return;
+			}
+		}
+	}
+
+	let mut source_map = None;
+	if let Some(source_name) = matches.value_of("Source") {
+		let source = match fs::read_to_string(source_name) {
+			Ok(o) => o,
+			Err(e) => {
+				println!("Problem reading file {}: {}", source_name, e);
+				return;
+			}
+		};
+		let mut assembler = Assembler::from_source(&source, source_name.to_string());
+		match assembler.compile(false) {
+			Ok(output) => {
+				let assembled: Vec<i64> =
+					output.iter().filter_map(|cell| cell.parse().ok()).collect();
+				if assembled != memory {
+					println!("Warning: {} doesn't assemble to {}", source_name, filename);
+				}
+			}
+			Err(errors) => {
+				for error in errors {
+					println!("{}", error);
+				}
+				return;
+			}
+		}
+		source_map = Some(assembler.source_map().clone());
+	}
+
+	let mut debugger = Debugger {
+		program: IntCodeProgram::from_memory(Memory::new(memory), input),
+		source_map,
+	};
+	debugger.show_location();
+	let stdin = io::stdin();
+	let mut last_command = String::new();
+	loop {
+		print!("(icc) ");
+		io::stdout().flush().unwrap();
+		let mut line = String::new();
+		if stdin.lock().read_line(&mut line).unwrap() == 0 {
+			break;
+		}
+		if line.trim().is_empty() {
+			line = last_command.clone();
+		} else {
+			last_command = line.clone();
+		}
+		match debugger.execute(&line) {
+			Ok(true) => {}
+			Ok(false) => break,
+			Err(e) => println!("{}", e),
+		}
+	}
+}
@@ -0,0 +1,82 @@
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// Counts cell 20 up from 0 to 3, outputting every value.
+const COUNT: &str = "1001,20,1,20,4,20,1007,20,3,21,1005,21,0,99";
+
+/// Writes `program` to a file named after the test and debugs it with
+/// `commands` as input. Returns everything the debugger printed.
+fn debug(name: &str, program: &str, commands: &str) -> String {
+	let path: PathBuf =
+		env::temp_dir().join(format!("icc-debug-{}-{}.icc", name, std::process::id()));
+	fs::write(&path, program).unwrap();
+	let mut child = Command::new(env!("CARGO_BIN_EXE_icc-debug"))
+		.arg(&path)
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		.spawn()
+		.unwrap();
+	child
+		.stdin
+		.take()
+		.unwrap()
+		.write_all(commands.as_bytes())
+		.unwrap();
+	let output = child.wait_with_output().unwrap();
+	fs::remove_file(&path).unwrap();
+	String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn continue_runs_to_the_next_breakpoint() {
+	let output = debug("break", COUNT, "b 4\nc\nc\nc\nq\n");
+	assert!(output.contains("Breakpoint 0 set at 4"));
+	assert_eq!(output.matches("Breakpoint 0 at 4").count(), 3);
+	assert!(output.contains("Output: 1"));
+	assert!(output.contains("Output: 2"));
+	assert!(!output.contains("Output: 3"));
+}
+
+#[test]
+fn continuing_from_a_breakpoint_reports_watchpoints() {
+	let output = debug("watch", COUNT, "b 0\nwatch 20 w\nc\nq\n");
+	// The first `ADD` runs from the breakpoint the program starts on.
+	assert!(output.contains("Watchpoint 1: 20 written by 0, value 1"));
+}
+
+#[test]
+fn next_stops_after_the_instruction() {
+	let output = debug("next", COUNT, "n\nregs\nq\n");
+	assert!(output.contains("ip = 4"));
+	assert!(output.contains("executed = 1"));
+}
+
+#[test]
+fn continue_reports_the_halt() {
+	let output = debug("halt", COUNT, "c\nq\n");
+	assert!(output.contains("Output: 3"));
+	assert!(output.contains("Program halted"));
+}
+
+#[test]
+fn negative_instruction_pointers_are_rejected() {
+	let output = debug("set", COUNT, "set ip=-1\nregs\nset ip=4\nregs\nq\n");
+	assert!(output.contains("Invalid instruction pointer <-1>"));
+	assert!(output.contains("ip = 0"));
+	assert!(output.contains("ip = 4"));
+}
+
+#[test]
+fn addresses_outside_of_memory_are_rejected() {
+	let output = debug(
+		"range",
+		COUNT,
+		"set mem[2000000000]=1\np mem[18446744073709551615]\nset mem[20]=7\np mem[20]\nq\n",
+	);
+	assert!(output.contains("Address 2000000000 is outside of a memory of 1073741824 cells"));
+	assert!(output.contains("Address 18446744073709551615 is out of range"));
+	assert!(output.contains("    20: 7"));
+}
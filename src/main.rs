@@ -1,53 +1,267 @@
 use std::fs;
+extern crate clap;
 extern crate icc;
+extern crate icc_assembler;
 
-use icc::interpreter::IntCodeProgram;
+use clap::{App, AppSettings, Arg};
+use icc::ascii::AsciiIo;
+use icc::coverage::Coverage;
+use icc::interpreter::{ExecutionEvent, IntCodeProgram, VmError};
+use icc::profile::Profiler;
+use icc::state::MachineState;
+use icc::trace::{JsonLinesTracer, Tracer};
 use icc::Memory;
+use icc_assembler::Assembler;
+use std::sync::{Arc, Mutex};
 use std::vec;
 
-use std::io;
+use std::io::{self, BufReader, BufWriter, Write};
+
+/// Word type programs are run with, picked by the `bigint` and `i128` features.
+#[cfg(feature = "bigint")]
+type Word = icc::word::BigInt;
+#[cfg(all(feature = "i128", not(feature = "bigint")))]
+type Word = i128;
+#[cfg(not(any(feature = "i128", feature = "bigint")))]
+type Word = i64;
 
 fn main() {
-	let args: Vec<String> = std::env::args().collect();
-	let arg_count = args.len();
-	if arg_count < 2 {
-		println!("Please enter a file name to execute");
-		return;
-	}
+	let matches = App::new("main")
+		.about("Runs an icc program")
+		.setting(AppSettings::AllowNegativeNumbers)
+		.arg(
+			Arg::with_name("Ascii")
+				.long("ascii")
+				.help("Reads input lines as text and prints outputs in 0..=127 as characters")
+				.takes_value(false),
+		)
+		.arg(
+			Arg::with_name("Trace")
+				.long("trace")
+				.value_name("TRACE")
+				.help("Records every executed instruction to a JSON Lines file")
+				.takes_value(true),
+		)
+		.arg(
+			Arg::with_name("Profile")
+				.long("profile")
+				.help("Prints the most executed addresses, the instruction mix and the most accessed cells")
+				.takes_value(false),
+		)
+		.arg(
+			Arg::with_name("Coverage")
+				.long("coverage")
+				.value_name("LCOV")
+				.help("Prints how often each source line ran and writes an lcov file")
+				.requires("Source")
+				.takes_value(true),
+		)
+		.arg(
+			Arg::with_name("Source")
+				.long("source")
+				.value_name("SOURCE")
+				.help("Assembly the program was compiled from, to show labels and source lines in reports")
+				.takes_value(true),
+		)
+		.arg(
+			Arg::with_name("SaveState")
+				.long("save-state")
+				.value_name("STATE")
+				.help("Saves the program's state to a file when stdin is closed while it waits for input")
+				.takes_value(true),
+		)
+		.arg(
+			Arg::with_name("LoadState")
+				.long("load-state")
+				.value_name("STATE")
+				.help("Resumes a program from a file written by --save-state instead of running a program file. Further input is read from stdin.")
+				.takes_value(true),
+		)
+		.arg(
+			Arg::with_name("Program")
+				.help("Sets the program to execute.")
+				.required_unless("LoadState")
+				.conflicts_with("LoadState")
+				.index(1),
+		)
+		.arg(
+			Arg::with_name("Input")
+				.help("Values passed to the program's first IN instructions.")
+				.multiple(true)
+				.index(2),
+		)
+		.get_matches();
+
 	let mut program_input = Vec::new();
-	if arg_count > 2 {
-		for i in 2..arg_count {
-			let temp = match args[i].parse() {
-				Ok(val) => val,
-				Err(e) => panic!("Error when parsing program input: {}", e),
+	for arg in matches.values_of("Input").into_iter().flatten() {
+		let temp = match arg.parse() {
+			Ok(val) => val,
+			Err(e) => panic!("Error when parsing program input: {}", e),
+		};
+		program_input.push(temp);
+	}
+
+	let mut program = match matches.value_of("LoadState") {
+		Some(state_name) => {
+			let file = match fs::File::open(state_name) {
+				Ok(f) => f,
+				Err(e) => {
+					println!("Problem reading file {}: {}", state_name, e);
+					return;
+				}
+			};
+			let state: MachineState<Word> = match MachineState::read_from(BufReader::new(file)) {
+				Ok(state) => state,
+				Err(e) => {
+					println!("Problem loading state {}: {}", state_name, e);
+					return;
+				}
 			};
-			program_input.push(temp);
-		}
-	}
-	let input = fs::read_to_string(&args[1])
-		.expect("Unable to reaad file.")
-		.replace(" ", "")
-		.replace(",\n", ",")
-		.replace("\n", ",");
-	let memory: vec::Vec<i64> = input.split(",").map(|cell| cell.parse().unwrap()).collect();
-	let memory = Memory::new(memory);
-
-	let program = IntCodeProgram::new(
-		memory.clone(),
-		program_input,
-		|val| println!("Program Output: {}", val),
-		|| {
-			println!("Please enter an integer value");
-			let stdin = io::stdin();
-			let mut input = String::new();
-			stdin.read_line(&mut input).unwrap();
-			input
-				.replace("\n", "")
-				.replace("\r", "")
-				.parse::<i64>()
-				.unwrap()
-		},
-	);
-	let result = program.run();
-	println!("{:?}", result);
+			IntCodeProgram::from_state(state)
+		}
+		None => {
+			let input = fs::read_to_string(matches.value_of("Program").unwrap())
+				.expect("Unable to reaad file.")
+				.replace(" ", "")
+				.replace(",\n", ",")
+				.replace("\n", ",");
+			let memory: vec::Vec<Word> =
+				input.split(",").map(|cell| cell.parse().unwrap()).collect();
+			IntCodeProgram::from_memory(Memory::from_words(memory), program_input)
+		}
+	};
+
+	let mut source_map = None;
+	if let Some(source_name) = matches.value_of("Source") {
+		let source = match fs::read_to_string(source_name) {
+			Ok(o) => o,
+			Err(e) => {
+				println!("Problem reading file {}: {}", source_name, e);
+				return;
+			}
+		};
+		let mut assembler = Assembler::from_source(&source, source_name.to_string());
+		if let Err(errors) = assembler.compile(false) {
+			for error in errors {
+				println!("{}", error);
+			}
+			return;
+		}
+		source_map = Some(assembler.source_map().clone());
+	}
+
+	let mut tracers: Vec<Box<dyn Tracer<Word> + Send>> = Vec::new();
+	let mut tracer = None;
+	if let Some(trace_name) = matches.value_of("Trace") {
+		let file = match fs::File::create(trace_name) {
+			Ok(f) => f,
+			Err(e) => {
+				println!("Problem creating file {}: {}", trace_name, e);
+				return;
+			}
+		};
+		let json_tracer = Arc::new(Mutex::new(JsonLinesTracer::new(BufWriter::new(file))));
+		tracers.push(Box::new(json_tracer.clone()));
+		tracer = Some((trace_name, json_tracer));
+	}
+	let mut profiler = None;
+	if matches.is_present("Profile") {
+		let shared_profiler = Arc::new(Mutex::new(Profiler::new()));
+		tracers.push(Box::new(shared_profiler.clone()));
+		profiler = Some(shared_profiler);
+	}
+	let mut coverage = None;
+	if let Some(lcov_name) = matches.value_of("Coverage") {
+		let shared_coverage = Arc::new(Mutex::new(Coverage::new()));
+		tracers.push(Box::new(shared_coverage.clone()));
+		coverage = Some((lcov_name, shared_coverage));
+	}
+	if !tracers.is_empty() {
+		program.set_tracer(tracers);
+	}
+
+	let ascii = matches.is_present("Ascii");
+	let result = if ascii {
+		run_ascii(&mut program)
+	} else {
+		loop {
+			match program.run_until_event() {
+				Ok(ExecutionEvent::Output(val)) => println!("Program Output: {}", val),
+				Ok(ExecutionEvent::Halted) => break Ok(program.take_output()),
+				Ok(ExecutionEvent::NeedsInput) => {
+					println!("Please enter an integer value");
+					let mut input = String::new();
+					if io::stdin().read_line(&mut input).unwrap() == 0 {
+						break Err(VmError::MissingInput {
+							ip: program.instruction_pointer(),
+						});
+					}
+					program.push_input(
+						input
+							.replace("\n", "")
+							.replace("\r", "")
+							.parse::<Word>()
+							.unwrap(),
+					);
+				}
+				Ok(_) => {}
+				Err(e) => break Err(e),
+			}
+		}
+	};
+	match result {
+		Ok(_) if ascii => {}
+		Ok(result) => println!("{:?}", result),
+		Err(VmError::MissingInput { .. }) if matches.is_present("SaveState") => {
+			let state_name = matches.value_of("SaveState").unwrap();
+			let saved = fs::File::create(state_name)
+				.and_then(|file| program.save_state().write_to(BufWriter::new(file)));
+			match saved {
+				Ok(()) => println!("Saved state to {}", state_name),
+				Err(e) => println!("Problem saving state {}: {}", state_name, e),
+			}
+		}
+		Err(e) => println!("Program failed: {}", e),
+	}
+	if let Some(profiler) = profiler {
+		print!(
+			"{}",
+			profiler.lock().unwrap().report(10, source_map.as_ref())
+		);
+	}
+	if let (Some((lcov_name, coverage)), Some(source_map)) = (coverage, source_map.as_ref()) {
+		let coverage = coverage.lock().unwrap();
+		print!("{}", coverage.report(source_map));
+		let written = fs::File::create(lcov_name)
+			.and_then(|file| coverage.write_lcov(source_map, BufWriter::new(file)));
+		if let Err(e) = written {
+			println!("Problem writing coverage {}: {}", lcov_name, e);
+		}
+	}
+	if let Some((trace_name, tracer)) = tracer {
+		if let Err(e) = tracer.lock().unwrap().finish() {
+			println!("Problem writing trace {}: {}", trace_name, e);
+		}
+	}
+}
+
+/// Runs a program that reads and prints text, feeding it lines from stdin.
+fn run_ascii(program: &mut IntCodeProgram<Word>) -> Result<Vec<Word>, VmError<Word>> {
+	let mut ascii = AsciiIo::new(program);
+	loop {
+		match ascii.run_until_event(|text| print!("{}", text))? {
+			ExecutionEvent::Halted => return Ok(ascii.program().take_output()),
+			ExecutionEvent::NeedsInput => {
+				io::stdout().flush().unwrap();
+				let mut input = String::new();
+				if io::stdin().read_line(&mut input).unwrap() == 0 {
+					return Err(VmError::MissingInput {
+						ip: ascii.program().instruction_pointer(),
+					});
+				}
+				ascii.push_line(input.trim_end_matches(&['\n', '\r'][..]));
+			}
+			_ => {}
+		}
+	}
 }